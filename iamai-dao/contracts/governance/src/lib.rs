@@ -1,8 +1,34 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{Token, TokenAccount};
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::program::invoke_signed;
+use anchor_lang::system_program;
+use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
+use iamai_staking::cpi::accounts::{LockDelegateRecordForVote, LockStakeForVote};
+use iamai_staking::program::IamaiStaking;
+use iamai_staking::{DelegateRecord as StakingDelegateRecord, UserStake, VoteEscrow};
 
 declare_id!("5kzjdRm4pHrTrqpijSB8QYE8tN9yCnmbHw49iX3DXc9y");
 
+// Size limits for a single instruction carried by a proposal.
+pub const MAX_TRANSACTION_ACCOUNTS: usize = 16;
+pub const MAX_TRANSACTION_DATA: usize = 512;
+
+// Token accounts (treasury, vaults) whose balances do not count towards quorum.
+pub const MAX_EXCLUDED_ACCOUNTS: usize = 8;
+
+// Staking tiers that can carry their own voting multiplier.
+pub const MAX_TIER_MULTIPLIERS: usize = 8;
+
+// Multiple-choice proposal limits.
+pub const MAX_OPTIONS: usize = 8;
+pub const MAX_OPTION_LABEL_LEN: usize = 50;
+
+// Veto council size; approvals are tracked as a bitmask.
+pub const MAX_COUNCIL_MEMBERS: usize = 9;
+
+// Number of ProposalType variants.
+pub const MAX_PROPOSAL_TYPES: usize = 3;
+
 #[program]
 pub mod iamai_governance {
     use super::*;
@@ -21,11 +47,338 @@ pub mod iamai_governance {
         governance.execution_delay = execution_delay;
         governance.proposal_count = 0;
         governance.is_initialized = true;
+        governance.excluded_accounts = Vec::new();
+        governance.vault = ctx.accounts.vault.key();
+        governance.staking_pool = Pubkey::default();
+        governance.tier_multipliers = Vec::new();
+        governance.quadratic_proposal_types = Vec::new();
+        governance.identity_attestor = Pubkey::default();
+        governance.council = Council {
+            members: Vec::new(),
+            threshold: 0,
+            version: 0,
+        };
+        Ok(())
+    }
+
+    // An empty council disables vetoes. Replacing the council discards veto
+    // approvals given by the previous one.
+    pub fn set_council(
+        ctx: Context<SetCouncil>,
+        members: Vec<Pubkey>,
+        threshold: u8,
+    ) -> Result<()> {
+        require!(
+            members.len() <= MAX_COUNCIL_MEMBERS
+                && (threshold as usize) <= members.len()
+                && (threshold > 0 || members.is_empty()),
+            ErrorCode::InvalidCouncil
+        );
+
+        let council = &mut ctx.accounts.governance.council;
+        council.members = members;
+        council.threshold = threshold;
+        council.version += 1;
+        Ok(())
+    }
+
+    // Proposals of the listed types count votes quadratically. When an
+    // identity attestor is set, quadratic votes require its attestation.
+    pub fn configure_quadratic_voting(
+        ctx: Context<ConfigureQuadraticVoting>,
+        quadratic_proposal_types: Vec<ProposalType>,
+        identity_attestor: Pubkey,
+    ) -> Result<()> {
+        require!(
+            quadratic_proposal_types.len() <= MAX_PROPOSAL_TYPES,
+            ErrorCode::InvalidProposalTypes
+        );
+
+        let governance = &mut ctx.accounts.governance;
+        governance.quadratic_proposal_types = quadratic_proposal_types;
+        governance.identity_attestor = identity_attestor;
+        Ok(())
+    }
+
+    pub fn attest_identity(ctx: Context<AttestIdentity>, voter: Pubkey) -> Result<()> {
+        let clock = Clock::get()?;
+        let identity_attestation = &mut ctx.accounts.identity_attestation;
+        identity_attestation.governance = ctx.accounts.governance.key();
+        identity_attestation.voter = voter;
+        identity_attestation.attestor = ctx.accounts.attestor.key();
+        identity_attestation.attested_at = clock.unix_timestamp;
+        Ok(())
+    }
+
+    pub fn revoke_identity(_ctx: Context<RevokeIdentity>) -> Result<()> {
+        Ok(())
+    }
+
+    // Stakes in tiers without a multiplier vote at 1x.
+    pub fn configure_staking_voting(
+        ctx: Context<ConfigureStakingVoting>,
+        staking_pool: Pubkey,
+        tier_multipliers: Vec<TierMultiplier>,
+    ) -> Result<()> {
+        require!(
+            tier_multipliers.len() <= MAX_TIER_MULTIPLIERS,
+            ErrorCode::TooManyTierMultipliers
+        );
+
+        let governance = &mut ctx.accounts.governance;
+        governance.staking_pool = staking_pool;
+        governance.tier_multipliers = tier_multipliers;
+        Ok(())
+    }
+
+    pub fn create_token_owner_record(ctx: Context<CreateTokenOwnerRecord>) -> Result<()> {
+        let token_owner_record = &mut ctx.accounts.token_owner_record;
+        token_owner_record.governance = ctx.accounts.governance.key();
+        token_owner_record.owner = ctx.accounts.owner.key();
+        token_owner_record.deposited_amount = 0;
+        token_owner_record.active_votes = 0;
+        token_owner_record.delegate = Pubkey::default();
+        token_owner_record.active_overrides = 0;
+        Ok(())
+    }
+
+    pub fn create_delegate_record(
+        ctx: Context<CreateDelegateRecord>,
+        delegate: Pubkey,
+    ) -> Result<()> {
+        let delegate_record = &mut ctx.accounts.delegate_record;
+        delegate_record.governance = ctx.accounts.governance.key();
+        delegate_record.delegate = delegate;
+        delegate_record.delegated_power = 0;
+        delegate_record.delegator_count = 0;
+        Ok(())
+    }
+
+    // Delegates the owner's deposited tokens, including any deposited later.
+    // Delegations only change while neither side has votes on active
+    // proposals and the owner has no overrides outstanding.
+    pub fn delegate_votes(ctx: Context<DelegateVotes>, delegate: Pubkey) -> Result<()> {
+        let token_owner_record = &mut ctx.accounts.token_owner_record;
+        let delegate_record = &mut ctx.accounts.delegate_record;
+        let delegation = &mut ctx.accounts.delegation;
+
+        require!(
+            delegate != ctx.accounts.owner.key(),
+            ErrorCode::InvalidDelegate
+        );
+        require!(
+            token_owner_record.deposited_amount > 0,
+            ErrorCode::InvalidAmount
+        );
+        require!(
+            token_owner_record.active_votes == 0
+                && ctx.accounts.delegate_token_owner_record.active_votes == 0,
+            ErrorCode::ActiveVotesOutstanding
+        );
+
+        delegation.governance = ctx.accounts.governance.key();
+        delegation.delegator = ctx.accounts.owner.key();
+        delegation.delegate = delegate;
+        delegation.amount = token_owner_record.deposited_amount;
+
+        token_owner_record.delegate = delegate;
+        delegate_record.delegated_power += delegation.amount;
+        delegate_record.delegator_count += 1;
+        Ok(())
+    }
+
+    pub fn undelegate_votes(ctx: Context<UndelegateVotes>) -> Result<()> {
+        let token_owner_record = &mut ctx.accounts.token_owner_record;
+        let delegate_record = &mut ctx.accounts.delegate_record;
+        let delegation = &ctx.accounts.delegation;
+
+        // An override still counted on some proposal must stay tied to the
+        // delegate it took power from
+        require!(
+            ctx.accounts.delegate_token_owner_record.active_votes == 0
+                && token_owner_record.active_overrides == 0,
+            ErrorCode::ActiveVotesOutstanding
+        );
+
+        token_owner_record.delegate = Pubkey::default();
+        delegate_record.delegated_power -= delegation.amount;
+        delegate_record.delegator_count -= 1;
+        Ok(())
+    }
+
+    // Lets a delegator vote directly on a proposal: their delegated weight is
+    // taken back from the delegate for this proposal only.
+    pub fn override_delegate_vote(ctx: Context<OverrideDelegateVote>) -> Result<()> {
+        let proposal = &mut ctx.accounts.proposal;
+        let delegation = &ctx.accounts.delegation;
+        let delegate_override = &mut ctx.accounts.delegate_override;
+        let vote_override = &mut ctx.accounts.vote_override;
+
+        let clock = Clock::get()?;
+        require!(
+            proposal.status == ProposalStatus::Active && clock.unix_timestamp <= proposal.end_time,
+            ErrorCode::ProposalNotActive
+        );
+
+        vote_override.proposal = proposal.key();
+        vote_override.delegator = delegation.delegator;
+        vote_override.delegate = delegation.delegate;
+        ctx.accounts.token_owner_record.active_overrides += 1;
+
+        delegate_override.proposal = proposal.key();
+        delegate_override.delegate = delegation.delegate;
+        delegate_override.overridden_power += delegation.amount;
+
+        // Take the weight back from a vote the delegate has already cast
+        let delegate_vote_info = ctx.accounts.delegate_vote_record.to_account_info();
+        if !delegate_vote_info.data_is_empty() {
+            require_keys_eq!(
+                *delegate_vote_info.owner,
+                crate::ID,
+                ErrorCode::InvalidDelegate
+            );
+            let mut delegate_vote =
+                VoteRecord::try_deserialize(&mut &delegate_vote_info.try_borrow_data()?[..])?;
+            let reclaimed_power = std::cmp::min(delegation.amount, delegate_vote.delegated_power);
+
+            remove_vote_tally(proposal, &delegate_vote);
+            reduce_vote_power(&mut delegate_vote, reclaimed_power, proposal.vote_mode);
+            add_vote_tally(proposal, &delegate_vote);
+
+            delegate_vote.try_serialize(&mut &mut delegate_vote_info.try_borrow_mut_data()?[..])?;
+        }
+        Ok(())
+    }
+
+    pub fn deposit_governing_tokens(
+        ctx: Context<DepositGoverningTokens>,
+        amount: u64,
+    ) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let token_owner_record = &mut ctx.accounts.token_owner_record;
+        token_owner_record.deposited_amount += amount;
+
+        // Delegated deposits grow the delegation with them
+        if token_owner_record.delegate != Pubkey::default() {
+            require!(
+                token_owner_record.active_overrides == 0,
+                ErrorCode::ActiveVotesOutstanding
+            );
+            let (Some(delegation), Some(delegate_record)) = (
+                ctx.accounts.delegation.as_mut(),
+                ctx.accounts.delegate_record.as_mut(),
+            ) else {
+                return err!(ErrorCode::DelegationAccountsRequired);
+            };
+            delegation.amount += amount;
+            delegate_record.delegated_power += amount;
+        }
+
+        // Transfer tokens to the governance vault
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.owner_token_account.to_account_info(),
+            to: ctx.accounts.vault.to_account_info(),
+            authority: ctx.accounts.owner.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer(cpi_ctx, amount)?;
+
+        Ok(())
+    }
+
+    // Deposits stay locked while any vote cast with them is still counted.
+    pub fn withdraw_governing_tokens(
+        ctx: Context<WithdrawGoverningTokens>,
+        amount: u64,
+    ) -> Result<()> {
+        let token_owner_record = &mut ctx.accounts.token_owner_record;
+
+        require!(
+            token_owner_record.active_votes == 0,
+            ErrorCode::ActiveVotesOutstanding
+        );
+        require!(
+            token_owner_record.delegate == Pubkey::default(),
+            ErrorCode::VotesDelegated
+        );
+        require!(
+            amount > 0 && amount <= token_owner_record.deposited_amount,
+            ErrorCode::InvalidAmount
+        );
+
+        token_owner_record.deposited_amount -= amount;
+
+        // Transfer tokens back to the owner
+        let governance_key = ctx.accounts.governance.key();
+        let seeds = &[
+            b"governance_vault",
+            governance_key.as_ref(),
+            &[ctx.bumps.vault],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.vault.to_account_info(),
+            to: ctx.accounts.owner_token_account.to_account_info(),
+            authority: ctx.accounts.vault.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, amount)?;
+
+        Ok(())
+    }
+
+    // Permissionless once voting has closed: releases the voter's deposit lock.
+    pub fn relinquish_vote(ctx: Context<RelinquishVote>) -> Result<()> {
+        let proposal = &ctx.accounts.proposal;
+        let vote_record = &mut ctx.accounts.vote_record;
+
+        let clock = Clock::get()?;
+        require!(
+            proposal.status != ProposalStatus::Active || clock.unix_timestamp > proposal.end_time,
+            ErrorCode::ProposalStillActive
+        );
+        require!(!vote_record.relinquished, ErrorCode::VoteAlreadyRelinquished);
+
+        vote_record.relinquished = true;
+        ctx.accounts.token_owner_record.active_votes -= 1;
+        Ok(())
+    }
+
+    // Permissionless once voting has closed: releases a delegator's override
+    // so they can change their delegation again.
+    pub fn relinquish_override(ctx: Context<RelinquishOverride>) -> Result<()> {
+        let proposal = &ctx.accounts.proposal;
+
+        let clock = Clock::get()?;
+        require!(
+            proposal.status != ProposalStatus::Active || clock.unix_timestamp > proposal.end_time,
+            ErrorCode::ProposalStillActive
+        );
+
+        ctx.accounts.token_owner_record.active_overrides -= 1;
+        Ok(())
+    }
+
+    pub fn set_excluded_accounts(
+        ctx: Context<SetExcludedAccounts>,
+        excluded_accounts: Vec<Pubkey>,
+    ) -> Result<()> {
+        require!(
+            excluded_accounts.len() <= MAX_EXCLUDED_ACCOUNTS,
+            ErrorCode::TooManyExcludedAccounts
+        );
+        ctx.accounts.governance.excluded_accounts = excluded_accounts;
         Ok(())
     }
 
-    pub fn create_proposal(
-        ctx: Context<CreateProposal>,
+    // The governance's excluded token accounts are passed in remaining_accounts,
+    // in the configured order, so their balances can be left out of quorum.
+    pub fn create_proposal<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CreateProposal<'info>>,
         title: String,
         description: String,
         proposal_type: ProposalType,
@@ -33,14 +386,40 @@ pub mod iamai_governance {
     ) -> Result<()> {
         let governance = &mut ctx.accounts.governance;
         let proposal = &mut ctx.accounts.proposal;
-        let user_token_account = &ctx.accounts.user_token_account;
+        let token_owner_record = &ctx.accounts.token_owner_record;
 
-        // Check if user has enough tokens
+        // Check if user has enough deposited tokens
         require!(
-            user_token_account.amount >= governance.min_tokens_for_proposal,
+            token_owner_record.deposited_amount >= governance.min_tokens_for_proposal,
             ErrorCode::InsufficientTokensForProposal
         );
 
+        // Snapshot the circulating supply that quorum is measured against
+        require!(
+            ctx.remaining_accounts.len() == governance.excluded_accounts.len(),
+            ErrorCode::InvalidExcludedAccounts
+        );
+        let mut excluded_amount = 0u64;
+        for (account_info, excluded_account) in ctx
+            .remaining_accounts
+            .iter()
+            .zip(governance.excluded_accounts.iter())
+        {
+            require_keys_eq!(
+                account_info.key(),
+                *excluded_account,
+                ErrorCode::InvalidExcludedAccounts
+            );
+            let token_account = Account::<TokenAccount>::try_from(account_info)?;
+            require_keys_eq!(
+                token_account.mint,
+                governance.token_mint,
+                ErrorCode::InvalidExcludedAccounts
+            );
+            excluded_amount += token_account.amount;
+        }
+        let supply_snapshot = ctx.accounts.token_mint.supply.saturating_sub(excluded_amount);
+
         let clock = Clock::get()?;
         let current_time = clock.unix_timestamp;
 
@@ -52,12 +431,28 @@ pub mod iamai_governance {
         proposal.proposal_type = proposal_type;
         proposal.votes_for = 0;
         proposal.votes_against = 0;
+        proposal.votes_abstain = 0;
         proposal.total_votes = 0;
+        proposal.total_voting_power = 0;
+        proposal.quadratic = governance.quadratic_proposal_types.contains(&proposal.proposal_type);
         proposal.start_time = current_time;
         proposal.end_time = current_time + voting_period;
         proposal.execution_time = 0;
         proposal.status = ProposalStatus::Active;
         proposal.quorum_reached = false;
+        proposal.transaction_count = 0;
+        proposal.executed_transaction_count = 0;
+        proposal.supply_snapshot = supply_snapshot;
+        proposal.treasury_transfer = None;
+        proposal.treasury_transfer_executed = false;
+        proposal.options = Vec::new();
+        proposal.vote_mode = VoteMode::SingleChoice;
+        proposal.outcome_rule = OutcomeRule::Plurality;
+        proposal.winning_option = None;
+        proposal.execution_option = 0;
+        proposal.veto_approvals = 0;
+        proposal.veto_council_version = governance.council.version;
+        proposal.votes_cast = 0;
 
         // Increment proposal count
         governance.proposal_count += 1;
@@ -65,33 +460,179 @@ pub mod iamai_governance {
         Ok(())
     }
 
-    pub fn vote_on_proposal(
-        ctx: Context<VoteOnProposal>,
-        support: bool,
-        voting_power: u64,
+    // Attaches the next instruction to a proposal, or to one of its options
+    // for multiple-choice proposals (`option_index` is 0 otherwise).
+    // Instructions can only be added before the first vote so voters see the
+    // final set.
+    pub fn add_proposal_transaction(
+        ctx: Context<AddProposalTransaction>,
+        option_index: u8,
+        program_id: Pubkey,
+        accounts: Vec<ProposalAccountMeta>,
+        data: Vec<u8>,
     ) -> Result<()> {
         let proposal = &mut ctx.accounts.proposal;
-        let vote_record = &mut ctx.accounts.vote_record;
-        let user_token_account = &ctx.accounts.user_token_account;
+        let proposal_transaction = &mut ctx.accounts.proposal_transaction;
 
         require!(
             proposal.status == ProposalStatus::Active,
             ErrorCode::ProposalNotActive
         );
+        require!(proposal.votes_cast == 0, ErrorCode::VotingStarted);
+        require!(
+            accounts.len() <= MAX_TRANSACTION_ACCOUNTS && data.len() <= MAX_TRANSACTION_DATA,
+            ErrorCode::TransactionTooLarge
+        );
+        require!(
+            (option_index as usize) < proposal.options.len()
+                || (proposal.options.is_empty() && option_index == 0),
+            ErrorCode::InvalidOption
+        );
 
-        let clock = Clock::get()?;
-        let current_time = clock.unix_timestamp;
+        proposal_transaction.proposal = proposal.key();
+        proposal_transaction.option_index = option_index;
+        proposal_transaction.index = proposal.next_transaction_index(option_index);
+        proposal_transaction.program_id = program_id;
+        proposal_transaction.accounts = accounts;
+        proposal_transaction.data = data;
+        proposal_transaction.executed = false;
+        proposal_transaction.executed_at = 0;
+
+        match proposal.options.get_mut(option_index as usize) {
+            Some(option) => option.transaction_count += 1,
+            None => proposal.transaction_count += 1,
+        }
+        Ok(())
+    }
+
+    // Turns a freshly created proposal into a multiple-choice proposal.
+    pub fn set_proposal_options(
+        ctx: Context<SetProposalOptions>,
+        option_labels: Vec<String>,
+        vote_mode: VoteMode,
+        outcome_rule: OutcomeRule,
+    ) -> Result<()> {
+        let proposal = &mut ctx.accounts.proposal;
 
         require!(
-            current_time >= proposal.start_time && current_time <= proposal.end_time,
-            ErrorCode::VotingPeriodEnded
+            proposal.status == ProposalStatus::Active,
+            ErrorCode::ProposalNotActive
+        );
+        require!(proposal.votes_cast == 0, ErrorCode::VotingStarted);
+        require!(
+            proposal.options.is_empty()
+                && proposal.transaction_count == 0
+                && proposal.treasury_transfer.is_none(),
+            ErrorCode::InvalidOption
         );
+        require!(
+            (2..=MAX_OPTIONS).contains(&option_labels.len())
+                && option_labels
+                    .iter()
+                    .all(|label| label.len() <= MAX_OPTION_LABEL_LEN),
+            ErrorCode::InvalidOption
+        );
+        if let OutcomeRule::Threshold { min_bps } = outcome_rule {
+            require!(
+                min_bps > 0 && min_bps <= 10000,
+                ErrorCode::InvalidOutcomeRule
+            );
+        }
+
+        proposal.options = option_labels
+            .into_iter()
+            .map(|label| ProposalOption {
+                label,
+                votes: 0,
+                transaction_count: 0,
+            })
+            .collect();
+        proposal.vote_mode = vote_mode;
+        proposal.outcome_rule = outcome_rule;
+        Ok(())
+    }
+
+    // Attaches a transfer out of the governance treasury to a Treasury
+    // proposal. `recipient` is the destination wallet for SOL (`mint` of
+    // None) or the destination token account for SPL tokens.
+    pub fn set_treasury_transfer(
+        ctx: Context<SetTreasuryTransfer>,
+        treasury_transfer: TreasuryTransfer,
+    ) -> Result<()> {
+        let proposal = &mut ctx.accounts.proposal;
 
-        // Verify voting power matches token balance
         require!(
-            voting_power <= user_token_account.amount,
-            ErrorCode::InsufficientVotingPower
+            proposal.status == ProposalStatus::Active,
+            ErrorCode::ProposalNotActive
+        );
+        require!(proposal.votes_cast == 0, ErrorCode::VotingStarted);
+        require!(
+            proposal.proposal_type == ProposalType::Treasury,
+            ErrorCode::NotTreasuryProposal
         );
+        require!(treasury_transfer.amount > 0, ErrorCode::InvalidAmount);
+        require!(proposal.options.is_empty(), ErrorCode::InvalidOption);
+
+        proposal.treasury_transfer = Some(treasury_transfer);
+        Ok(())
+    }
+
+    // One spend limit per mint; SOL uses the default pubkey as its mint.
+    pub fn create_spend_limit(
+        ctx: Context<CreateSpendLimit>,
+        mint: Pubkey,
+        max_per_proposal: u64,
+        max_per_epoch: u64,
+        epoch_duration: i64, // seconds
+    ) -> Result<()> {
+        require!(epoch_duration > 0, ErrorCode::InvalidSpendLimit);
+
+        let clock = Clock::get()?;
+        let spend_limit = &mut ctx.accounts.spend_limit;
+        spend_limit.governance = ctx.accounts.governance.key();
+        spend_limit.mint = mint;
+        spend_limit.max_per_proposal = max_per_proposal;
+        spend_limit.max_per_epoch = max_per_epoch;
+        spend_limit.epoch_duration = epoch_duration;
+        spend_limit.epoch_start = clock.unix_timestamp;
+        spend_limit.spent_in_epoch = 0;
+        Ok(())
+    }
+
+    pub fn update_spend_limit(
+        ctx: Context<UpdateSpendLimit>,
+        max_per_proposal: u64,
+        max_per_epoch: u64,
+    ) -> Result<()> {
+        let spend_limit = &mut ctx.accounts.spend_limit;
+        spend_limit.max_per_proposal = max_per_proposal;
+        spend_limit.max_per_epoch = max_per_epoch;
+        Ok(())
+    }
+
+    // The voter's stakes in the governance's staking pool may be passed in
+    // remaining_accounts to add their weight to the deposited tokens; see
+    // lock_staked_voting_power for the layout.
+    pub fn vote_on_proposal<'info>(
+        ctx: Context<'_, '_, 'info, 'info, VoteOnProposal<'info>>,
+        choice: VoteChoice,
+        voting_power: u64,
+    ) -> Result<()> {
+        verify_identity(ctx.accounts)?;
+        let delegated_power = available_delegated_power(ctx.accounts)?;
+        let staked_power = lock_staked_voting_power(ctx.accounts, ctx.remaining_accounts)?;
+        let proposal = &mut ctx.accounts.proposal;
+        let vote_record = &mut ctx.accounts.vote_record;
+        let token_owner_record = &mut ctx.accounts.token_owner_record;
+
+        require!(proposal.options.is_empty(), ErrorCode::InvalidOption);
+        validate_vote(
+            proposal,
+            token_owner_record,
+            voting_power,
+            staked_power,
+            delegated_power,
+        )?;
 
         // Check if user already voted
         require!(!vote_record.has_voted, ErrorCode::AlreadyVoted);
@@ -99,21 +640,178 @@ pub mod iamai_governance {
         // Record vote
         vote_record.voter = ctx.accounts.voter.key();
         vote_record.proposal = proposal.key();
-        vote_record.support = support;
+        vote_record.choice = Some(choice);
         vote_record.voting_power = voting_power;
+        vote_record.staked_power = staked_power;
+        vote_record.delegated_power = std::cmp::min(voting_power, delegated_power);
         vote_record.has_voted = true;
+        vote_record.relinquished = false;
+        vote_record.option_votes = Vec::new();
+        token_owner_record.active_votes += 1;
 
         // Update proposal vote counts
-        if support {
-            proposal.votes_for += voting_power;
-        } else {
-            proposal.votes_against += voting_power;
+        add_vote_tally(proposal, vote_record);
+
+        Ok(())
+    }
+
+    // Replaces a for/against/abstain vote. Multiple-choice votes are changed
+    // by withdrawing and voting again. Staked power stays as locked when the
+    // vote was cast.
+    pub fn change_vote(
+        ctx: Context<ChangeVote>,
+        choice: VoteChoice,
+        voting_power: u64,
+    ) -> Result<()> {
+        let proposal = &mut ctx.accounts.proposal;
+        let vote_record = &mut ctx.accounts.vote_record;
+
+        require!(proposal.options.is_empty(), ErrorCode::InvalidOption);
+        validate_vote(
+            proposal,
+            &ctx.accounts.token_owner_record,
+            voting_power,
+            vote_record.staked_power,
+            vote_record.delegated_power,
+        )?;
+
+        remove_vote_tally(proposal, vote_record);
+        vote_record.choice = Some(choice);
+        vote_record.voting_power = voting_power;
+        vote_record.delegated_power = std::cmp::min(voting_power, vote_record.delegated_power);
+        add_vote_tally(proposal, vote_record);
+
+        Ok(())
+    }
+
+    // Removes the vote from the tallies and returns the record's rent.
+    pub fn withdraw_vote(ctx: Context<WithdrawVote>) -> Result<()> {
+        let proposal = &mut ctx.accounts.proposal;
+
+        let clock = Clock::get()?;
+        require!(
+            proposal.status == ProposalStatus::Active && clock.unix_timestamp <= proposal.end_time,
+            ErrorCode::ProposalNotActive
+        );
+
+        remove_vote_tally(proposal, &ctx.accounts.vote_record);
+        ctx.accounts.token_owner_record.active_votes -= 1;
+
+        Ok(())
+    }
+
+    // Votes on a multiple-choice proposal. Single-choice takes one option,
+    // approval gives every listed option the full voting power, weighted
+    // split divides the voting power across the listed options, and ranked
+    // takes options in order of preference with the full voting power each.
+    pub fn vote_on_options<'info>(
+        ctx: Context<'_, '_, 'info, 'info, VoteOnProposal<'info>>,
+        option_votes: Vec<OptionVote>,
+    ) -> Result<()> {
+        verify_identity(ctx.accounts)?;
+        let delegated_power = available_delegated_power(ctx.accounts)?;
+        let staked_power = lock_staked_voting_power(ctx.accounts, ctx.remaining_accounts)?;
+        let proposal = &mut ctx.accounts.proposal;
+        let vote_record = &mut ctx.accounts.vote_record;
+        let token_owner_record = &mut ctx.accounts.token_owner_record;
+
+        require!(!proposal.options.is_empty(), ErrorCode::InvalidOption);
+        require!(!option_votes.is_empty(), ErrorCode::InvalidOption);
+        for (i, option_vote) in option_votes.iter().enumerate() {
+            require!(
+                (option_vote.option_index as usize) < proposal.options.len()
+                    && option_vote.voting_power > 0
+                    && option_votes[..i]
+                        .iter()
+                        .all(|other| other.option_index != option_vote.option_index),
+                ErrorCode::InvalidOption
+            );
         }
-        proposal.total_votes += voting_power;
 
+        let voting_power = match proposal.vote_mode {
+            VoteMode::SingleChoice => {
+                require!(option_votes.len() == 1, ErrorCode::InvalidOption);
+                option_votes[0].voting_power
+            }
+            VoteMode::Approval | VoteMode::Ranked => {
+                let voting_power = option_votes[0].voting_power;
+                require!(
+                    option_votes
+                        .iter()
+                        .all(|option_vote| option_vote.voting_power == voting_power),
+                    ErrorCode::InvalidOption
+                );
+                voting_power
+            }
+            VoteMode::WeightedSplit => option_votes
+                .iter()
+                .try_fold(0u64, |total, option_vote| total.checked_add(option_vote.voting_power))
+                .ok_or(ErrorCode::InsufficientVotingPower)?,
+        };
+
+        validate_vote(
+            proposal,
+            token_owner_record,
+            voting_power,
+            staked_power,
+            delegated_power,
+        )?;
+
+        // Record vote
+        vote_record.voter = ctx.accounts.voter.key();
+        vote_record.proposal = proposal.key();
+        vote_record.choice = None;
+        vote_record.voting_power = voting_power;
+        vote_record.staked_power = staked_power;
+        vote_record.delegated_power = std::cmp::min(voting_power, delegated_power);
+        vote_record.has_voted = true;
+        vote_record.relinquished = false;
+        token_owner_record.active_votes += 1;
+
+        // Update option tallies
+        vote_record.option_votes = option_votes;
+        add_vote_tally(proposal, vote_record);
+
+        Ok(())
+    }
+
+    pub fn cancel_proposal(ctx: Context<CancelProposal>) -> Result<()> {
+        let proposal = &mut ctx.accounts.proposal;
+
+        require!(
+            proposal.status == ProposalStatus::Active,
+            ErrorCode::ProposalNotActive
+        );
+        // Once voting has ended the outcome is left to finalize_proposal
+        require!(
+            Clock::get()?.unix_timestamp <= proposal.end_time,
+            ErrorCode::VotingPeriodEnded
+        );
+
+        proposal.status = ProposalStatus::Cancelled;
         Ok(())
     }
 
+    // Records a council member's approval to veto a passed proposal during
+    // its execution delay. The proposal is vetoed once the threshold is met.
+    pub fn veto_proposal(ctx: Context<VetoProposal>) -> Result<()> {
+        let council = &ctx.accounts.governance.council;
+        let proposal = &mut ctx.accounts.proposal;
+
+        require!(
+            proposal.status == ProposalStatus::Passed,
+            ErrorCode::ProposalNotPassed
+        );
+
+        let clock = Clock::get()?;
+        require!(
+            clock.unix_timestamp < proposal.execution_time,
+            ErrorCode::VetoWindowClosed
+        );
+
+        proposal.approve_veto(council, &ctx.accounts.council_member.key())
+    }
+
     pub fn finalize_proposal(ctx: Context<FinalizeProposal>) -> Result<()> {
         let governance = &ctx.accounts.governance;
         let proposal = &mut ctx.accounts.proposal;
@@ -131,14 +829,26 @@ pub mod iamai_governance {
             ErrorCode::VotingPeriodNotEnded
         );
 
-        // Calculate quorum (simplified - in practice would need total supply)
-        let total_supply = 1_000_000_000u64; // Mock total supply
-        let required_quorum = (total_supply * governance.quorum_percentage as u64) / 100;
-        
-        proposal.quorum_reached = proposal.total_votes >= required_quorum;
+        // Quorum is a share of the supply snapshotted at creation; both sides
+        // are in base units, so the mint's decimals cancel out. Quadratic
+        // proposals still measure turnout in raw voting power
+        let required_quorum =
+            (proposal.supply_snapshot as u128 * governance.quorum_percentage as u128) / 100;
+
+        proposal.quorum_reached = proposal.total_voting_power as u128 >= required_quorum;
 
         // Determine proposal outcome
-        if proposal.quorum_reached && proposal.votes_for > proposal.votes_against {
+        let passed = if proposal.options.is_empty() {
+            proposal.votes_for > proposal.votes_against
+        } else {
+            proposal.winning_option = proposal.leading_option();
+            if let Some(winning_option) = proposal.winning_option {
+                proposal.execution_option = winning_option;
+            }
+            proposal.winning_option.is_some()
+        };
+
+        if proposal.quorum_reached && passed {
             proposal.status = ProposalStatus::Passed;
             proposal.execution_time = current_time + governance.execution_delay;
         } else {
@@ -148,7 +858,13 @@ pub mod iamai_governance {
         Ok(())
     }
 
-    pub fn execute_proposal(ctx: Context<ExecuteProposal>) -> Result<()> {
+    // Executes the proposal one step per call: its treasury transfer first,
+    // then each stored instruction in order. An instruction's accounts are
+    // passed in remaining_accounts in the stored order, followed by the
+    // target program.
+    pub fn execute_proposal<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExecuteProposal<'info>>,
+    ) -> Result<()> {
         let proposal = &mut ctx.accounts.proposal;
 
         require!(
@@ -164,100 +880,1213 @@ pub mod iamai_governance {
             ErrorCode::ExecutionDelayNotMet
         );
 
-        // Execute proposal based on type
-        match proposal.proposal_type {
-            ProposalType::Treasury => {
-                // Handle treasury proposal execution
-                msg!("Executing treasury proposal: {}", proposal.title);
-            }
-            ProposalType::Technical => {
-                // Handle technical proposal execution
-                msg!("Executing technical proposal: {}", proposal.title);
-            }
-            ProposalType::Community => {
-                // Handle community proposal execution
-                msg!("Executing community proposal: {}", proposal.title);
-            }
+        let governance_key = ctx.accounts.governance.key();
+
+        if let Some(treasury_transfer) = proposal
+            .treasury_transfer
+            .clone()
+            .filter(|_| !proposal.treasury_transfer_executed)
+        {
+            let spend_limit = ctx
+                .accounts
+                .spend_limit
+                .as_mut()
+                .ok_or(ErrorCode::TreasuryAccountsRequired)?;
+            spend_limit.record_spend(&treasury_transfer, current_time)?;
+
+            let recipient = ctx
+                .accounts
+                .recipient
+                .as_ref()
+                .ok_or(ErrorCode::TreasuryAccountsRequired)?;
+            require_keys_eq!(
+                recipient.key(),
+                treasury_transfer.recipient,
+                ErrorCode::InvalidTreasuryRecipient
+            );
+
+            let seeds = &[
+                b"treasury",
+                governance_key.as_ref(),
+                &[ctx.bumps.treasury],
+            ];
+            let signer = &[&seeds[..]];
+
+            match treasury_transfer.mint {
+                None => {
+                    let system_program = ctx
+                        .accounts
+                        .system_program
+                        .as_ref()
+                        .ok_or(ErrorCode::TreasuryAccountsRequired)?;
+                    let cpi_accounts = system_program::Transfer {
+                        from: ctx.accounts.treasury.to_account_info(),
+                        to: recipient.to_account_info(),
+                    };
+                    let cpi_ctx = CpiContext::new_with_signer(
+                        system_program.to_account_info(),
+                        cpi_accounts,
+                        signer,
+                    );
+                    system_program::transfer(cpi_ctx, treasury_transfer.amount)?;
+                }
+                Some(mint) => {
+                    let treasury_token_account = ctx
+                        .accounts
+                        .treasury_token_account
+                        .as_ref()
+                        .ok_or(ErrorCode::TreasuryAccountsRequired)?;
+                    let token_program = ctx
+                        .accounts
+                        .token_program
+                        .as_ref()
+                        .ok_or(ErrorCode::TreasuryAccountsRequired)?;
+                    require_keys_eq!(
+                        treasury_token_account.mint,
+                        mint,
+                        ErrorCode::InvalidTreasuryAccount
+                    );
+
+                    let cpi_accounts = Transfer {
+                        from: treasury_token_account.to_account_info(),
+                        to: recipient.to_account_info(),
+                        authority: ctx.accounts.treasury.to_account_info(),
+                    };
+                    let cpi_ctx = CpiContext::new_with_signer(
+                        token_program.to_account_info(),
+                        cpi_accounts,
+                        signer,
+                    );
+                    token::transfer(cpi_ctx, treasury_transfer.amount)?;
+                }
+            }
+
+            proposal.treasury_transfer_executed = true;
+        } else if proposal.executed_transaction_count < proposal.executable_transaction_count() {
+            let proposal_transaction = ctx
+                .accounts
+                .proposal_transaction
+                .as_mut()
+                .ok_or(ErrorCode::InvalidProposalTransaction)?;
+            require!(
+                proposal_transaction.index == proposal.executed_transaction_count,
+                ErrorCode::InvalidProposalTransaction
+            );
+            require!(
+                !proposal_transaction.executed,
+                ErrorCode::TransactionAlreadyExecuted
+            );
+
+            let account_count = proposal_transaction.accounts.len();
+            require!(
+                ctx.remaining_accounts.len() > account_count
+                    && ctx.remaining_accounts[account_count].key() == proposal_transaction.program_id,
+                ErrorCode::InvalidTransactionAccounts
+            );
+            for (account_meta, account_info) in proposal_transaction
+                .accounts
+                .iter()
+                .zip(ctx.remaining_accounts.iter())
+            {
+                require_keys_eq!(
+                    account_meta.pubkey,
+                    account_info.key(),
+                    ErrorCode::InvalidTransactionAccounts
+                );
+            }
+
+            let instruction = Instruction {
+                program_id: proposal_transaction.program_id,
+                accounts: proposal_transaction
+                    .accounts
+                    .iter()
+                    .map(|account_meta| AccountMeta {
+                        pubkey: account_meta.pubkey,
+                        is_signer: account_meta.is_signer,
+                        is_writable: account_meta.is_writable,
+                    })
+                    .collect(),
+                data: proposal_transaction.data.clone(),
+            };
+
+            let mut account_infos = ctx.remaining_accounts.to_vec();
+            account_infos.push(ctx.accounts.governance_executor.to_account_info());
+
+            let seeds = &[
+                b"executor",
+                governance_key.as_ref(),
+                &[ctx.bumps.governance_executor],
+            ];
+            let signer = &[&seeds[..]];
+            invoke_signed(&instruction, &account_infos, signer)?;
+
+            proposal_transaction.executed = true;
+            proposal_transaction.executed_at = current_time;
+            proposal.executed_transaction_count += 1;
+        } else {
+            // Proposals without actions are signalling only
+            msg!("Executing proposal: {}", proposal.title);
+        }
+
+        let treasury_pending =
+            proposal.treasury_transfer.is_some() && !proposal.treasury_transfer_executed;
+        if !treasury_pending
+            && proposal.executed_transaction_count == proposal.executable_transaction_count()
+        {
+            proposal.status = ProposalStatus::Executed;
+        }
+        Ok(())
+    }
+}
+
+// Adds a recorded vote to the proposal's tallies. Abstentions count towards
+// quorum but not towards the outcome.
+fn add_vote_tally(proposal: &mut Proposal, vote_record: &VoteRecord) {
+    let quadratic = proposal.quadratic;
+    let weight = |voting_power| counted_votes(voting_power, quadratic);
+    match vote_record.choice {
+        Some(VoteChoice::For) => proposal.votes_for += weight(vote_record.voting_power),
+        Some(VoteChoice::Against) => proposal.votes_against += weight(vote_record.voting_power),
+        Some(VoteChoice::Abstain) => proposal.votes_abstain += weight(vote_record.voting_power),
+        None => {
+            for (option_index, votes) in proposal.option_weights(vote_record) {
+                proposal.options[option_index].votes += votes;
+            }
+        }
+    }
+    proposal.total_votes += proposal.total_vote_weight(vote_record);
+    proposal.total_voting_power += vote_record.voting_power;
+    proposal.votes_cast += 1;
+}
+
+fn remove_vote_tally(proposal: &mut Proposal, vote_record: &VoteRecord) {
+    let quadratic = proposal.quadratic;
+    let weight = |voting_power| counted_votes(voting_power, quadratic);
+    match vote_record.choice {
+        Some(VoteChoice::For) => proposal.votes_for -= weight(vote_record.voting_power),
+        Some(VoteChoice::Against) => proposal.votes_against -= weight(vote_record.voting_power),
+        Some(VoteChoice::Abstain) => proposal.votes_abstain -= weight(vote_record.voting_power),
+        None => {
+            for (option_index, votes) in proposal.option_weights(vote_record) {
+                proposal.options[option_index].votes -= votes;
+            }
+        }
+    }
+    proposal.total_votes -= proposal.total_vote_weight(vote_record);
+    proposal.total_voting_power -= vote_record.voting_power;
+}
+
+// Quadratic votes need an attestation from the governance's identity
+// attestor, if one is configured, to make splitting tokens across wallets
+// harder.
+fn verify_identity(accounts: &VoteOnProposal) -> Result<()> {
+    let governance = &accounts.governance;
+    if accounts.proposal.quadratic && governance.identity_attestor != Pubkey::default() {
+        let identity_attestation = accounts
+            .identity_attestation
+            .as_ref()
+            .ok_or(ErrorCode::IdentityAttestationRequired)?;
+        require_keys_eq!(
+            identity_attestation.attestor,
+            governance.identity_attestor,
+            ErrorCode::IdentityAttestationRequired
+        );
+    }
+    Ok(())
+}
+
+// Votes counted for `voting_power` on a linear or quadratic proposal.
+//
+// A vote is rooted as one block, delegated power included, so a delegate
+// with many delegators counts less than those delegators voting directly.
+// That is deliberate: delegation concentrates power, which is what the
+// quadratic mode damps, and delegators are not identity-attested, so rooting
+// each delegation separately would let a holder split tokens across
+// delegating wallets and get linear weight back. Delegators who want their
+// own square root can override and vote directly.
+fn counted_votes(voting_power: u64, quadratic: bool) -> u64 {
+    if quadratic {
+        isqrt(voting_power)
+    } else {
+        voting_power
+    }
+}
+
+// Integer square root, rounded down.
+fn isqrt(value: u64) -> u64 {
+    if value < 2 {
+        return value;
+    }
+    // Newton's method from an initial guess at or above the root
+    let mut root = 1u64 << ((64 - value.leading_zeros()).div_ceil(2));
+    loop {
+        let next = (root + value / root) / 2;
+        if next >= root {
+            return root;
+        }
+        root = next;
+    }
+}
+
+// Checks the proposal is open and the voter holds `voting_power`. Voting
+// power is backed by tokens deposited with the governance plus locked staked
+// tokens; the two are held in separate vaults.
+fn validate_vote(
+    proposal: &Proposal,
+    token_owner_record: &TokenOwnerRecord,
+    voting_power: u64,
+    staked_power: u64,
+    delegated_power: u64,
+) -> Result<()> {
+    require!(
+        proposal.status == ProposalStatus::Active,
+        ErrorCode::ProposalNotActive
+    );
+
+    let clock = Clock::get()?;
+    let current_time = clock.unix_timestamp;
+
+    require!(
+        current_time >= proposal.start_time && current_time <= proposal.end_time,
+        ErrorCode::VotingPeriodEnded
+    );
+
+    require!(
+        voting_power as u128
+            <= token_owner_record.deposited_amount as u128
+                + staked_power as u128
+                + delegated_power as u128,
+        ErrorCode::InsufficientVotingPower
+    );
+    Ok(())
+}
+
+// Power delegated to the voter that is still theirs on this proposal, i.e.
+// not taken back by delegators voting directly. Delegators themselves must
+// record an override before voting.
+fn available_delegated_power(accounts: &VoteOnProposal) -> Result<u64> {
+    let delegate = accounts.token_owner_record.delegate;
+    if delegate != Pubkey::default() {
+        require!(
+            accounts
+                .vote_override
+                .as_ref()
+                .is_some_and(|vote_override| vote_override.delegate == delegate),
+            ErrorCode::VotesDelegated
+        );
+    }
+
+    let Some(delegate_record) = accounts.delegate_record.as_ref() else {
+        return Ok(0);
+    };
+    let delegate_override = accounts
+        .delegate_override
+        .as_ref()
+        .ok_or(ErrorCode::DelegateOverrideRequired)?;
+
+    let overridden_power = if delegate_override.data_is_empty() {
+        0
+    } else {
+        require_keys_eq!(
+            *delegate_override.owner,
+            crate::ID,
+            ErrorCode::DelegateOverrideRequired
+        );
+        DelegateOverride::try_deserialize(&mut &delegate_override.try_borrow_data()?[..])?
+            .overridden_power
+    };
+    Ok(delegate_record.delegated_power.saturating_sub(overridden_power))
+}
+
+// Lowers a recorded vote by `amount`. Approval and ranked votes give every
+// option the full power, so each option drops by `amount`; otherwise options
+// are reduced in order.
+fn reduce_vote_power(vote_record: &mut VoteRecord, amount: u64, vote_mode: VoteMode) {
+    let mut remaining = amount;
+    for option_vote in vote_record.option_votes.iter_mut() {
+        if matches!(vote_mode, VoteMode::Approval | VoteMode::Ranked) {
+            option_vote.voting_power -= amount;
+        } else {
+            let reduction = std::cmp::min(option_vote.voting_power, remaining);
+            option_vote.voting_power -= reduction;
+            remaining -= reduction;
         }
+    }
+    vote_record.voting_power -= amount;
+    vote_record.delegated_power -= amount;
+}
+
+// Weight of the voter's stakes, each scaled by its tier's multiplier, plus
+// stake power delegated to the voter in the staking program (counted at 1x).
+// Every stake counted is locked in the staking program until the proposal
+// ends and marked as used on this proposal, and the delegate record is
+// locked too. Tokens behind a vote therefore cannot be unstaked, moved or
+// handed over as a position NFT to vote again. A vote_escrow owned by the
+// voter adds its veIAMAI power as of the proposal start.
+//
+// remaining_accounts carry, per stake: the UserStake, its StakeVoteMarker
+// PDA and, for a tokenized stake, the voter's position token account.
+fn lock_staked_voting_power<'info>(
+    accounts: &VoteOnProposal<'info>,
+    stake_accounts: &'info [AccountInfo<'info>],
+) -> Result<u64> {
+    if stake_accounts.is_empty()
+        && accounts.staking_delegate_record.is_none()
+        && accounts.vote_escrow.is_none()
+    {
+        return Ok(0);
+    }
+
+    let governance = &accounts.governance;
+    let voter = accounts.voter.key();
+    let unlock_time = accounts.proposal.end_time;
+    require!(
+        governance.staking_pool != Pubkey::default(),
+        ErrorCode::StakingVotingNotEnabled
+    );
+    let staking_program = accounts
+        .staking_program
+        .as_ref()
+        .ok_or(ErrorCode::StakingProgramRequired)?;
+
+    let mut counted_stakes: Vec<Pubkey> = Vec::new();
+    let mut staked_power = 0u128;
+
+    let mut stake_accounts = stake_accounts.iter();
+    while let Some(stake_info) = stake_accounts.next() {
+        let marker_info = stake_accounts
+            .next()
+            .ok_or(ErrorCode::InvalidStakeAccount)?;
+        require!(
+            !counted_stakes.contains(stake_info.key),
+            ErrorCode::DuplicateStakeAccount
+        );
+        counted_stakes.push(stake_info.key());
+
+        // Deserializing checks the account is owned by the staking program
+        let user_stake = Account::<UserStake>::try_from(stake_info)?;
+        require_keys_eq!(
+            user_stake.pool,
+            governance.staking_pool,
+            ErrorCode::InvalidStakeAccount
+        );
+
+        // Whoever holds a tokenized position votes with it
+        let position_info = if user_stake.position_mint == Pubkey::default() {
+            require_keys_eq!(user_stake.user, voter, ErrorCode::InvalidStakeAccount);
+            None
+        } else {
+            let position_info = stake_accounts
+                .next()
+                .ok_or(ErrorCode::InvalidStakeAccount)?;
+            let position = Account::<TokenAccount>::try_from(position_info)?;
+            require!(
+                position.mint == user_stake.position_mint
+                    && position.owner == voter
+                    && position.amount == 1,
+                ErrorCode::InvalidStakeAccount
+            );
+            Some(position_info.clone())
+        };
+        require!(
+            user_stake.is_active
+                && !user_stake.is_unbonding
+                && user_stake.delegate == Pubkey::default(),
+            ErrorCode::StakeNotEligible
+        );
+
+        mark_stake_voted(accounts, stake_info.key, marker_info)?;
+        iamai_staking::cpi::lock_stake_for_vote(
+            CpiContext::new(
+                staking_program.to_account_info(),
+                LockStakeForVote {
+                    user_stake: stake_info.clone(),
+                    position_token_account: position_info,
+                    user: accounts.voter.to_account_info(),
+                },
+            ),
+            unlock_time,
+        )?;
+
+        let multiplier_bps = governance
+            .tier_multipliers
+            .iter()
+            .find(|tier_multiplier| tier_multiplier.tier == user_stake.tier)
+            .map_or(10000, |tier_multiplier| tier_multiplier.multiplier_bps);
+        staked_power += user_stake.amount as u128 * multiplier_bps as u128 / 10000;
+    }
+
+    if let Some(delegate_record) = accounts.staking_delegate_record.as_ref() {
+        require!(
+            delegate_record.pool == governance.staking_pool && delegate_record.delegate == voter,
+            ErrorCode::InvalidDelegate
+        );
+        iamai_staking::cpi::lock_delegate_record_for_vote(
+            CpiContext::new(
+                staking_program.to_account_info(),
+                LockDelegateRecordForVote {
+                    delegate_record: delegate_record.to_account_info(),
+                    delegate: accounts.voter.to_account_info(),
+                },
+            ),
+            unlock_time,
+        )?;
+        staked_power += delegate_record.delegated_power as u128;
+    }
+
+    // Escrowed tokens stay locked until unlock_time, so power is read at the
+    // proposal start rather than locked like a stake
+    if let Some(vote_escrow) = accounts.vote_escrow.as_ref() {
+        require!(
+            vote_escrow.pool == governance.staking_pool && vote_escrow.owner == voter,
+            ErrorCode::InvalidVoteEscrow
+        );
+        staked_power += vote_escrow.voting_power_at(accounts.proposal.start_time)? as u128;
+    }
+
+    Ok(u64::try_from(staked_power).unwrap_or(u64::MAX))
+}
+
+// Records that a stake has backed a vote on the proposal. A stake marked by
+// another voter cannot be counted again; its own voter may reuse it after
+// withdrawing and re-casting their vote.
+fn mark_stake_voted<'info>(
+    accounts: &VoteOnProposal<'info>,
+    user_stake: &Pubkey,
+    marker_info: &'info AccountInfo<'info>,
+) -> Result<()> {
+    let proposal_key = accounts.proposal.key();
+    let voter = accounts.voter.key();
+    let (marker_key, bump) = Pubkey::find_program_address(
+        &[b"stake_vote", proposal_key.as_ref(), user_stake.as_ref()],
+        &crate::ID,
+    );
+    require_keys_eq!(marker_key, *marker_info.key, ErrorCode::InvalidStakeAccount);
+
+    if *marker_info.owner == crate::ID {
+        let marker = StakeVoteMarker::try_deserialize(&mut &marker_info.try_borrow_data()?[..])?;
+        require_keys_eq!(marker.voter, voter, ErrorCode::StakeAlreadyVoted);
+        return Ok(());
+    }
+
+    // Allocate the marker like `init` would, tolerating lamports sent ahead
+    let space = 8 + StakeVoteMarker::INIT_SPACE;
+    let rent = Rent::get()?.minimum_balance(space);
+    let signer_seeds: &[&[&[u8]]] = &[&[
+        b"stake_vote",
+        proposal_key.as_ref(),
+        user_stake.as_ref(),
+        &[bump],
+    ]];
+    let system_program = accounts.system_program.to_account_info();
+    if marker_info.lamports() < rent {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.clone(),
+                system_program::Transfer {
+                    from: accounts.voter.to_account_info(),
+                    to: marker_info.clone(),
+                },
+            ),
+            rent - marker_info.lamports(),
+        )?;
+    }
+    system_program::allocate(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            system_program::Allocate {
+                account_to_allocate: marker_info.clone(),
+            },
+            signer_seeds,
+        ),
+        space as u64,
+    )?;
+    system_program::assign(
+        CpiContext::new_with_signer(
+            system_program,
+            system_program::Assign {
+                account_to_assign: marker_info.clone(),
+            },
+            signer_seeds,
+        ),
+        &crate::ID,
+    )?;
+
+    let marker = StakeVoteMarker {
+        proposal: proposal_key,
+        user_stake: *user_stake,
+        voter,
+    };
+    marker.try_serialize(&mut &mut marker_info.try_borrow_mut_data()?[..])?;
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct InitializeGovernance<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Governance::INIT_SPACE
+    )]
+    pub governance: Account<'info, Governance>,
+    
+    pub token_mint: Account<'info, anchor_spl::token::Mint>,
+
+    #[account(
+        init,
+        payer = authority,
+        token::mint = token_mint,
+        token::authority = vault,
+        seeds = [b"governance_vault", governance.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct CreateTokenOwnerRecord<'info> {
+    pub governance: Account<'info, Governance>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + TokenOwnerRecord::INIT_SPACE,
+        seeds = [b"token_owner", governance.key().as_ref(), owner.key().as_ref()],
+        bump,
+    )]
+    pub token_owner_record: Account<'info, TokenOwnerRecord>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delegate: Pubkey)]
+pub struct CreateDelegateRecord<'info> {
+    pub governance: Account<'info, Governance>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + DelegateRecord::INIT_SPACE,
+        seeds = [b"delegate_record", governance.key().as_ref(), delegate.as_ref()],
+        bump,
+    )]
+    pub delegate_record: Account<'info, DelegateRecord>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delegate: Pubkey)]
+pub struct DelegateVotes<'info> {
+    pub governance: Account<'info, Governance>,
+
+    #[account(
+        mut,
+        seeds = [b"token_owner", governance.key().as_ref(), owner.key().as_ref()],
+        bump,
+    )]
+    pub token_owner_record: Account<'info, TokenOwnerRecord>,
+
+    #[account(
+        seeds = [b"token_owner", governance.key().as_ref(), delegate.as_ref()],
+        bump,
+    )]
+    pub delegate_token_owner_record: Account<'info, TokenOwnerRecord>,
+
+    #[account(
+        mut,
+        seeds = [b"delegate_record", governance.key().as_ref(), delegate.as_ref()],
+        bump,
+    )]
+    pub delegate_record: Account<'info, DelegateRecord>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + Delegation::INIT_SPACE,
+        seeds = [b"delegation", governance.key().as_ref(), owner.key().as_ref()],
+        bump,
+    )]
+    pub delegation: Account<'info, Delegation>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UndelegateVotes<'info> {
+    pub governance: Account<'info, Governance>,
+
+    #[account(
+        mut,
+        seeds = [b"token_owner", governance.key().as_ref(), owner.key().as_ref()],
+        bump,
+    )]
+    pub token_owner_record: Account<'info, TokenOwnerRecord>,
+
+    #[account(
+        seeds = [b"token_owner", governance.key().as_ref(), delegation.delegate.as_ref()],
+        bump,
+    )]
+    pub delegate_token_owner_record: Account<'info, TokenOwnerRecord>,
+
+    #[account(
+        mut,
+        seeds = [b"delegate_record", governance.key().as_ref(), delegation.delegate.as_ref()],
+        bump,
+    )]
+    pub delegate_record: Account<'info, DelegateRecord>,
+
+    #[account(
+        mut,
+        close = owner,
+        seeds = [b"delegation", governance.key().as_ref(), owner.key().as_ref()],
+        bump,
+    )]
+    pub delegation: Account<'info, Delegation>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct OverrideDelegateVote<'info> {
+    pub governance: Account<'info, Governance>,
+
+    #[account(mut, has_one = governance)]
+    pub proposal: Account<'info, Proposal>,
+
+    #[account(
+        seeds = [b"delegation", governance.key().as_ref(), delegator.key().as_ref()],
+        bump,
+    )]
+    pub delegation: Account<'info, Delegation>,
+
+    #[account(
+        init_if_needed,
+        payer = delegator,
+        space = 8 + DelegateOverride::INIT_SPACE,
+        seeds = [b"delegate_override", proposal.key().as_ref(), delegation.delegate.as_ref()],
+        bump,
+    )]
+    pub delegate_override: Account<'info, DelegateOverride>,
+
+    #[account(
+        init,
+        payer = delegator,
+        space = 8 + VoteOverride::INIT_SPACE,
+        seeds = [b"vote_override", proposal.key().as_ref(), delegator.key().as_ref()],
+        bump,
+    )]
+    pub vote_override: Account<'info, VoteOverride>,
+
+    /// CHECK: the delegate's vote record, if they have voted; checked in the handler
+    #[account(
+        mut,
+        seeds = [b"vote", proposal.key().as_ref(), delegation.delegate.as_ref()],
+        bump,
+    )]
+    pub delegate_vote_record: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub delegator: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    #[account(
+        mut,
+        seeds = [b"token_owner", governance.key().as_ref(), delegator.key().as_ref()],
+        bump,
+    )]
+    pub token_owner_record: Account<'info, TokenOwnerRecord>,
+}
+
+#[derive(Accounts)]
+pub struct DepositGoverningTokens<'info> {
+    pub governance: Account<'info, Governance>,
+
+    #[account(
+        mut,
+        seeds = [b"token_owner", governance.key().as_ref(), owner.key().as_ref()],
+        bump,
+    )]
+    pub token_owner_record: Account<'info, TokenOwnerRecord>,
+
+    #[account(
+        mut,
+        seeds = [b"governance_vault", governance.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub owner_token_account: Account<'info, TokenAccount>,
+
+    pub owner: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+
+    // Required while the owner's votes are delegated
+    #[account(
+        mut,
+        seeds = [b"delegation", governance.key().as_ref(), owner.key().as_ref()],
+        bump,
+    )]
+    pub delegation: Option<Account<'info, Delegation>>,
+
+    #[account(
+        mut,
+        seeds = [b"delegate_record", governance.key().as_ref(), token_owner_record.delegate.as_ref()],
+        bump,
+    )]
+    pub delegate_record: Option<Account<'info, DelegateRecord>>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawGoverningTokens<'info> {
+    pub governance: Account<'info, Governance>,
+
+    #[account(
+        mut,
+        seeds = [b"token_owner", governance.key().as_ref(), owner.key().as_ref()],
+        bump,
+    )]
+    pub token_owner_record: Account<'info, TokenOwnerRecord>,
+
+    #[account(
+        mut,
+        seeds = [b"governance_vault", governance.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub owner_token_account: Account<'info, TokenAccount>,
+
+    pub owner: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct RelinquishVote<'info> {
+    pub proposal: Account<'info, Proposal>,
+
+    #[account(
+        mut,
+        seeds = [b"vote", proposal.key().as_ref(), vote_record.voter.as_ref()],
+        bump,
+    )]
+    pub vote_record: Account<'info, VoteRecord>,
+
+    #[account(
+        mut,
+        seeds = [b"token_owner", proposal.governance.as_ref(), vote_record.voter.as_ref()],
+        bump,
+    )]
+    pub token_owner_record: Account<'info, TokenOwnerRecord>,
+}
+
+#[derive(Accounts)]
+pub struct RelinquishOverride<'info> {
+    pub proposal: Account<'info, Proposal>,
+
+    #[account(
+        mut,
+        close = delegator,
+        seeds = [b"vote_override", proposal.key().as_ref(), vote_override.delegator.as_ref()],
+        bump,
+    )]
+    pub vote_override: Account<'info, VoteOverride>,
+
+    #[account(
+        mut,
+        seeds = [b"token_owner", proposal.governance.as_ref(), vote_override.delegator.as_ref()],
+        bump,
+    )]
+    pub token_owner_record: Account<'info, TokenOwnerRecord>,
+
+    /// CHECK: receives the override's rent
+    #[account(mut, address = vote_override.delegator)]
+    pub delegator: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureStakingVoting<'info> {
+    #[account(mut, has_one = authority)]
+    pub governance: Account<'info, Governance>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureQuadraticVoting<'info> {
+    #[account(mut, has_one = authority)]
+    pub governance: Account<'info, Governance>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(voter: Pubkey)]
+pub struct AttestIdentity<'info> {
+    #[account(
+        constraint = governance.identity_attestor == attestor.key() @ ErrorCode::Unauthorized,
+    )]
+    pub governance: Account<'info, Governance>,
+
+    #[account(
+        init,
+        payer = attestor,
+        space = 8 + IdentityAttestation::INIT_SPACE,
+        seeds = [b"identity", governance.key().as_ref(), voter.as_ref()],
+        bump,
+    )]
+    pub identity_attestation: Account<'info, IdentityAttestation>,
+
+    #[account(mut)]
+    pub attestor: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeIdentity<'info> {
+    #[account(
+        mut,
+        close = attestor,
+        has_one = governance,
+        has_one = attestor,
+    )]
+    pub identity_attestation: Account<'info, IdentityAttestation>,
+
+    pub governance: Account<'info, Governance>,
+
+    #[account(mut)]
+    pub attestor: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetCouncil<'info> {
+    #[account(mut, has_one = authority)]
+    pub governance: Account<'info, Governance>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetExcludedAccounts<'info> {
+    #[account(mut, has_one = authority)]
+    pub governance: Account<'info, Governance>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreateProposal<'info> {
+    #[account(mut)]
+    pub governance: Account<'info, Governance>,
+    
+    #[account(
+        init,
+        payer = proposer,
+        space = 8 + Proposal::INIT_SPACE
+    )]
+    pub proposal: Account<'info, Proposal>,
+
+    #[account(address = governance.token_mint)]
+    pub token_mint: Account<'info, Mint>,
+
+    #[account(
+        seeds = [b"token_owner", governance.key().as_ref(), proposer.key().as_ref()],
+        bump,
+    )]
+    pub token_owner_record: Account<'info, TokenOwnerRecord>,
+    
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(option_index: u8)]
+pub struct AddProposalTransaction<'info> {
+    #[account(mut, has_one = proposer)]
+    pub proposal: Account<'info, Proposal>,
+
+    #[account(
+        init,
+        payer = proposer,
+        space = 8 + ProposalTransaction::INIT_SPACE,
+        seeds = [
+            b"proposal_tx",
+            proposal.key().as_ref(),
+            option_index.to_le_bytes().as_ref(),
+            proposal.next_transaction_index(option_index).to_le_bytes().as_ref(),
+        ],
+        bump,
+    )]
+    pub proposal_transaction: Account<'info, ProposalTransaction>,
+
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct VoteOnProposal<'info> {
+    pub governance: Account<'info, Governance>,
+
+    #[account(mut, has_one = governance)]
+    pub proposal: Account<'info, Proposal>,
+    
+    #[account(
+        init,
+        payer = voter,
+        space = 8 + VoteRecord::INIT_SPACE,
+        seeds = [b"vote", proposal.key().as_ref(), voter.key().as_ref()],
+        bump,
+    )]
+    pub vote_record: Account<'info, VoteRecord>,
+
+    #[account(
+        mut,
+        seeds = [b"token_owner", proposal.governance.as_ref(), voter.key().as_ref()],
+        bump,
+    )]
+    pub token_owner_record: Account<'info, TokenOwnerRecord>,
+
+    #[account(
+        seeds = [b"delegate_record", governance.key().as_ref(), voter.key().as_ref()],
+        bump,
+    )]
+    pub delegate_record: Option<Account<'info, DelegateRecord>>,
+
+    /// CHECK: may be uninitialized; deserialized in available_delegated_power
+    #[account(
+        seeds = [b"delegate_override", proposal.key().as_ref(), voter.key().as_ref()],
+        bump,
+    )]
+    pub delegate_override: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        seeds = [b"vote_override", proposal.key().as_ref(), voter.key().as_ref()],
+        bump,
+    )]
+    pub vote_override: Option<Account<'info, VoteOverride>>,
+
+    #[account(
+        seeds = [b"identity", governance.key().as_ref(), voter.key().as_ref()],
+        bump,
+    )]
+    pub identity_attestation: Option<Account<'info, IdentityAttestation>>,
+    
+    #[account(mut)]
+    pub voter: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+
+    // Needed to lock stakes and the staking delegate record being voted with
+    pub staking_program: Option<Program<'info, IamaiStaking>>,
+
+    #[account(mut)]
+    pub staking_delegate_record: Option<Account<'info, StakingDelegateRecord>>,
+
+    pub vote_escrow: Option<Account<'info, VoteEscrow>>,
+}
+
+#[derive(Accounts)]
+pub struct ChangeVote<'info> {
+    pub governance: Account<'info, Governance>,
+
+    #[account(mut, has_one = governance)]
+    pub proposal: Account<'info, Proposal>,
+
+    #[account(
+        mut,
+        seeds = [b"vote", proposal.key().as_ref(), voter.key().as_ref()],
+        bump,
+    )]
+    pub vote_record: Account<'info, VoteRecord>,
+
+    #[account(
+        seeds = [b"token_owner", governance.key().as_ref(), voter.key().as_ref()],
+        bump,
+    )]
+    pub token_owner_record: Account<'info, TokenOwnerRecord>,
+
+    pub voter: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawVote<'info> {
+    #[account(mut)]
+    pub proposal: Account<'info, Proposal>,
+
+    #[account(
+        mut,
+        close = voter,
+        seeds = [b"vote", proposal.key().as_ref(), voter.key().as_ref()],
+        bump,
+    )]
+    pub vote_record: Account<'info, VoteRecord>,
+
+    #[account(
+        mut,
+        seeds = [b"token_owner", proposal.governance.as_ref(), voter.key().as_ref()],
+        bump,
+    )]
+    pub token_owner_record: Account<'info, TokenOwnerRecord>,
+
+    #[account(mut)]
+    pub voter: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelProposal<'info> {
+    #[account(mut, has_one = proposer)]
+    pub proposal: Account<'info, Proposal>,
+
+    pub proposer: Signer<'info>,
+}
 
-        proposal.status = ProposalStatus::Executed;
-        Ok(())
-    }
+#[derive(Accounts)]
+pub struct VetoProposal<'info> {
+    pub governance: Account<'info, Governance>,
+
+    #[account(mut, has_one = governance)]
+    pub proposal: Account<'info, Proposal>,
+
+    pub council_member: Signer<'info>,
 }
 
 #[derive(Accounts)]
-pub struct InitializeGovernance<'info> {
-    #[account(
-        init,
-        payer = authority,
-        space = 8 + Governance::INIT_SPACE
-    )]
+pub struct FinalizeProposal<'info> {
     pub governance: Account<'info, Governance>,
     
-    pub token_mint: Account<'info, anchor_spl::token::Mint>,
-    
-    #[account(mut)]
-    pub authority: Signer<'info>,
-    
-    pub system_program: Program<'info, System>,
+    #[account(mut, has_one = governance)]
+    pub proposal: Account<'info, Proposal>,
 }
 
 #[derive(Accounts)]
-pub struct CreateProposal<'info> {
-    #[account(mut)]
+pub struct ExecuteProposal<'info> {
     pub governance: Account<'info, Governance>,
-    
+
+    #[account(mut, has_one = governance)]
+    pub proposal: Account<'info, Proposal>,
+
     #[account(
-        init,
-        payer = proposer,
-        space = 8 + Proposal::INIT_SPACE
+        mut,
+        seeds = [
+            b"proposal_tx",
+            proposal.key().as_ref(),
+            proposal.execution_option.to_le_bytes().as_ref(),
+            proposal.executed_transaction_count.to_le_bytes().as_ref(),
+        ],
+        bump,
     )]
-    pub proposal: Account<'info, Proposal>,
-    
-    pub user_token_account: Account<'info, TokenAccount>,
-    
+    pub proposal_transaction: Option<Account<'info, ProposalTransaction>>,
+
+    /// CHECK: PDA that signs proposal instructions on behalf of the governance
+    #[account(
+        seeds = [b"executor", governance.key().as_ref()],
+        bump,
+    )]
+    pub governance_executor: UncheckedAccount<'info>,
+
+    /// CHECK: PDA holding treasury SOL and owning treasury token accounts
+    #[account(
+        mut,
+        seeds = [b"treasury", governance.key().as_ref()],
+        bump,
+    )]
+    pub treasury: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"spend_limit", governance.key().as_ref(), spend_limit.mint.as_ref()],
+        bump,
+        has_one = governance,
+    )]
+    pub spend_limit: Option<Account<'info, TreasurySpendLimit>>,
+
+    #[account(
+        mut,
+        token::authority = treasury,
+    )]
+    pub treasury_token_account: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: checked against the proposal's treasury transfer recipient
     #[account(mut)]
-    pub proposer: Signer<'info>,
+    pub recipient: Option<UncheckedAccount<'info>>,
     
-    pub system_program: Program<'info, System>,
+    pub executor: Signer<'info>,
+    pub token_program: Option<Program<'info, Token>>,
+    pub system_program: Option<Program<'info, System>>,
 }
 
 #[derive(Accounts)]
-pub struct VoteOnProposal<'info> {
-    #[account(mut)]
+pub struct SetProposalOptions<'info> {
+    #[account(mut, has_one = proposer)]
     pub proposal: Account<'info, Proposal>,
-    
+
+    pub proposer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetTreasuryTransfer<'info> {
+    #[account(mut, has_one = proposer)]
+    pub proposal: Account<'info, Proposal>,
+
+    pub proposer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(mint: Pubkey)]
+pub struct CreateSpendLimit<'info> {
+    #[account(has_one = authority)]
+    pub governance: Account<'info, Governance>,
+
     #[account(
         init,
-        payer = voter,
-        space = 8 + VoteRecord::INIT_SPACE,
-        seeds = [b"vote", proposal.key().as_ref(), voter.key().as_ref()],
+        payer = authority,
+        space = 8 + TreasurySpendLimit::INIT_SPACE,
+        seeds = [b"spend_limit", governance.key().as_ref(), mint.as_ref()],
         bump,
     )]
-    pub vote_record: Account<'info, VoteRecord>,
-    
-    pub user_token_account: Account<'info, TokenAccount>,
-    
+    pub spend_limit: Account<'info, TreasurySpendLimit>,
+
     #[account(mut)]
-    pub voter: Signer<'info>,
-    
+    pub authority: Signer<'info>,
+
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct FinalizeProposal<'info> {
+pub struct UpdateSpendLimit<'info> {
+    #[account(has_one = authority)]
     pub governance: Account<'info, Governance>,
-    
-    #[account(mut)]
-    pub proposal: Account<'info, Proposal>,
-}
 
-#[derive(Accounts)]
-pub struct ExecuteProposal<'info> {
-    #[account(mut)]
-    pub proposal: Account<'info, Proposal>,
-    
-    pub executor: Signer<'info>,
+    #[account(mut, has_one = governance)]
+    pub spend_limit: Account<'info, TreasurySpendLimit>,
+
+    pub authority: Signer<'info>,
 }
 
 #[account]
@@ -270,6 +2099,97 @@ pub struct Governance {
     pub execution_delay: i64,
     pub proposal_count: u64,
     pub is_initialized: bool,
+    #[max_len(MAX_EXCLUDED_ACCOUNTS)]
+    pub excluded_accounts: Vec<Pubkey>,
+    pub vault: Pubkey,
+    pub staking_pool: Pubkey,
+    #[max_len(MAX_TIER_MULTIPLIERS)]
+    pub tier_multipliers: Vec<TierMultiplier>,
+    #[max_len(MAX_PROPOSAL_TYPES)]
+    pub quadratic_proposal_types: Vec<ProposalType>,
+    pub identity_attestor: Pubkey,
+    pub council: Council,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
+pub struct Council {
+    #[max_len(MAX_COUNCIL_MEMBERS)]
+    pub members: Vec<Pubkey>,
+    pub threshold: u8,
+    pub version: u32, // bumped on every change
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct IdentityAttestation {
+    pub governance: Pubkey,
+    pub voter: Pubkey,
+    pub attestor: Pubkey,
+    pub attested_at: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
+pub struct TierMultiplier {
+    pub tier: Pubkey,
+    pub multiplier_bps: u16,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct TokenOwnerRecord {
+    pub governance: Pubkey,
+    pub owner: Pubkey,
+    pub deposited_amount: u64,
+    pub active_votes: u32,
+    pub delegate: Pubkey,
+    // Overrides of the delegate not yet relinquished
+    pub active_overrides: u32,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Delegation {
+    pub governance: Pubkey,
+    pub delegator: Pubkey,
+    pub delegate: Pubkey,
+    pub amount: u64,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct DelegateRecord {
+    pub governance: Pubkey,
+    pub delegate: Pubkey,
+    pub delegated_power: u64,
+    pub delegator_count: u32,
+}
+
+// Delegated power taken back from a delegate on one proposal by delegators
+// voting directly.
+#[account]
+#[derive(InitSpace)]
+pub struct DelegateOverride {
+    pub proposal: Pubkey,
+    pub delegate: Pubkey,
+    pub overridden_power: u64,
+}
+
+// Marks a delegator as voting directly on a proposal, instead of through
+// `delegate`.
+#[account]
+#[derive(InitSpace)]
+pub struct VoteOverride {
+    pub proposal: Pubkey,
+    pub delegator: Pubkey,
+    pub delegate: Pubkey,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct StakeVoteMarker {
+    pub proposal: Pubkey,
+    pub user_stake: Pubkey,
+    pub voter: Pubkey,
 }
 
 #[account]
@@ -284,12 +2204,227 @@ pub struct Proposal {
     pub proposal_type: ProposalType,
     pub votes_for: u64,
     pub votes_against: u64,
+    pub votes_abstain: u64,
     pub total_votes: u64,
+    pub total_voting_power: u64,
+    pub quadratic: bool,
     pub start_time: i64,
     pub end_time: i64,
     pub execution_time: i64,
     pub status: ProposalStatus,
     pub quorum_reached: bool,
+    pub transaction_count: u16,
+    pub executed_transaction_count: u16,
+    pub supply_snapshot: u64,
+    pub treasury_transfer: Option<TreasuryTransfer>,
+    pub treasury_transfer_executed: bool,
+    #[max_len(MAX_OPTIONS)]
+    pub options: Vec<ProposalOption>,
+    pub vote_mode: VoteMode,
+    pub outcome_rule: OutcomeRule,
+    pub winning_option: Option<u8>,
+    pub execution_option: u8,
+    pub veto_approvals: u16, // bitmask over council member positions
+    pub veto_council_version: u32,
+    // Votes recorded so far, including zero-power and withdrawn ones; never
+    // decremented, so the proposal's contents stay frozen once voting starts
+    pub votes_cast: u64,
+}
+
+impl Proposal {
+    // Weight a vote adds to `total_votes`: the vote counted once however many
+    // options it lists or splits across.
+    fn total_vote_weight(&self, vote_record: &VoteRecord) -> u64 {
+        counted_votes(vote_record.voting_power, self.quadratic)
+    }
+
+    // Votes a multiple-choice vote adds to each option it lists. A weighted
+    // split divides the counted vote in proportion to the allocations, so on
+    // a quadratic proposal splitting doesn't take more than one square root.
+    // Ranked votes give the option at rank r (from 0) a (n - r) / n share of
+    // the vote, where n is the number of options, so a first choice gets it
+    // all.
+    fn option_weights(&self, vote_record: &VoteRecord) -> Vec<(usize, u64)> {
+        let counted = counted_votes(vote_record.voting_power, self.quadratic) as u128;
+        let voting_power = vote_record.voting_power as u128;
+        let option_count = self.options.len() as u128;
+        vote_record
+            .option_votes
+            .iter()
+            .enumerate()
+            .map(|(rank, option_vote)| {
+                let votes = match self.vote_mode {
+                    VoteMode::WeightedSplit if voting_power > 0 => {
+                        counted * option_vote.voting_power as u128 / voting_power
+                    }
+                    VoteMode::WeightedSplit => 0,
+                    VoteMode::Ranked => counted * (option_count - rank as u128) / option_count,
+                    VoteMode::SingleChoice | VoteMode::Approval => counted,
+                };
+                (option_vote.option_index as usize, votes as u64)
+            })
+            .collect()
+    }
+
+    // Sets the member's bit in `veto_approvals` and vetoes the proposal at
+    // the council's threshold. Bits are positions in the council's member
+    // list, so approvals given under an earlier council are dropped.
+    fn approve_veto(&mut self, council: &Council, member: &Pubkey) -> Result<()> {
+        if self.veto_council_version != council.version {
+            self.veto_approvals = 0;
+            self.veto_council_version = council.version;
+        }
+
+        let member_index = council
+            .members
+            .iter()
+            .position(|council_member| council_member == member)
+            .ok_or(ErrorCode::NotCouncilMember)?;
+        let member_bit = 1u16 << member_index;
+        require!(
+            self.veto_approvals & member_bit == 0,
+            ErrorCode::AlreadyApprovedVeto
+        );
+
+        self.veto_approvals |= member_bit;
+        if self.veto_approvals.count_ones() >= council.threshold as u32 {
+            self.status = ProposalStatus::Vetoed;
+        }
+        Ok(())
+    }
+
+    // Instructions to run on execution: the winning option's for
+    // multiple-choice proposals.
+    fn executable_transaction_count(&self) -> u16 {
+        self.next_transaction_index(self.execution_option)
+    }
+
+    fn next_transaction_index(&self, option_index: u8) -> u16 {
+        self.options
+            .get(option_index as usize)
+            .map_or(self.transaction_count, |option| option.transaction_count)
+    }
+
+    // The option with the most votes, if it is the unique leader and meets
+    // the outcome rule.
+    fn leading_option(&self) -> Option<u8> {
+        let (leader, leading_votes) = self
+            .options
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, option)| option.votes)
+            .map(|(index, option)| (index, option.votes))?;
+        let tied = self
+            .options
+            .iter()
+            .enumerate()
+            .any(|(index, option)| index != leader && option.votes == leading_votes);
+        if tied || leading_votes == 0 {
+            return None;
+        }
+
+        match self.outcome_rule {
+            OutcomeRule::Plurality => Some(leader as u8),
+            OutcomeRule::Threshold { min_bps } => {
+                let meets_threshold = leading_votes as u128 * 10000
+                    >= self.total_votes as u128 * min_bps as u128;
+                meets_threshold.then_some(leader as u8)
+            }
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
+pub struct ProposalOption {
+    #[max_len(MAX_OPTION_LABEL_LEN)]
+    pub label: String,
+    pub votes: u64,
+    pub transaction_count: u16,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub struct OptionVote {
+    pub option_index: u8,
+    pub voting_power: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum VoteMode {
+    SingleChoice,
+    Approval,
+    WeightedSplit,
+    Ranked,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum OutcomeRule {
+    Plurality,
+    Threshold { min_bps: u16 },
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
+pub struct TreasuryTransfer {
+    pub mint: Option<Pubkey>,
+    pub recipient: Pubkey,
+    pub amount: u64,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct TreasurySpendLimit {
+    pub governance: Pubkey,
+    pub mint: Pubkey,
+    pub max_per_proposal: u64,
+    pub max_per_epoch: u64,
+    pub epoch_duration: i64,
+    pub epoch_start: i64,
+    pub spent_in_epoch: u64,
+}
+
+impl TreasurySpendLimit {
+    fn record_spend(&mut self, treasury_transfer: &TreasuryTransfer, timestamp: i64) -> Result<()> {
+        require_keys_eq!(
+            self.mint,
+            treasury_transfer.mint.unwrap_or_default(),
+            ErrorCode::InvalidSpendLimit
+        );
+
+        // Start a fresh window once the current one has elapsed
+        if timestamp >= self.epoch_start + self.epoch_duration {
+            self.epoch_start = timestamp;
+            self.spent_in_epoch = 0;
+        }
+
+        let spent_in_epoch = self.spent_in_epoch + treasury_transfer.amount;
+        require!(
+            treasury_transfer.amount <= self.max_per_proposal && spent_in_epoch <= self.max_per_epoch,
+            ErrorCode::SpendLimitExceeded
+        );
+        self.spent_in_epoch = spent_in_epoch;
+        Ok(())
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct ProposalTransaction {
+    pub proposal: Pubkey,
+    pub option_index: u8,
+    pub index: u16,
+    pub program_id: Pubkey,
+    #[max_len(MAX_TRANSACTION_ACCOUNTS)]
+    pub accounts: Vec<ProposalAccountMeta>,
+    #[max_len(MAX_TRANSACTION_DATA)]
+    pub data: Vec<u8>,
+    pub executed: bool,
+    pub executed_at: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
+pub struct ProposalAccountMeta {
+    pub pubkey: Pubkey,
+    pub is_signer: bool,
+    pub is_writable: bool,
 }
 
 #[account]
@@ -297,9 +2432,22 @@ pub struct Proposal {
 pub struct VoteRecord {
     pub voter: Pubkey,
     pub proposal: Pubkey,
-    pub support: bool,
+    pub choice: Option<VoteChoice>, // None for multiple-choice votes
     pub voting_power: u64,
+    // Power from stakes and staking delegation locked for this vote
+    pub staked_power: u64,
+    pub delegated_power: u64,
     pub has_voted: bool,
+    pub relinquished: bool,
+    #[max_len(MAX_OPTIONS)]
+    pub option_votes: Vec<OptionVote>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum VoteChoice {
+    For,
+    Against,
+    Abstain,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
@@ -315,6 +2463,8 @@ pub enum ProposalStatus {
     Passed,
     Rejected,
     Executed,
+    Vetoed,
+    Cancelled,
 }
 
 #[error_code]
@@ -335,4 +2485,342 @@ pub enum ErrorCode {
     ProposalNotPassed,
     #[msg("Execution delay not met")]
     ExecutionDelayNotMet,
+    #[msg("Voting has already started")]
+    VotingStarted,
+    #[msg("Proposal transaction is too large")]
+    TransactionTooLarge,
+    #[msg("Invalid proposal transaction")]
+    InvalidProposalTransaction,
+    #[msg("Proposal transaction already executed")]
+    TransactionAlreadyExecuted,
+    #[msg("Accounts do not match the proposal transaction")]
+    InvalidTransactionAccounts,
+    #[msg("Too many excluded accounts")]
+    TooManyExcludedAccounts,
+    #[msg("Excluded accounts do not match the governance configuration")]
+    InvalidExcludedAccounts,
+    #[msg("Invalid amount")]
+    InvalidAmount,
+    #[msg("Deposit is locked by votes on active proposals")]
+    ActiveVotesOutstanding,
+    #[msg("Proposal voting is still open")]
+    ProposalStillActive,
+    #[msg("Vote has already been relinquished")]
+    VoteAlreadyRelinquished,
+    #[msg("Too many tier multipliers")]
+    TooManyTierMultipliers,
+    #[msg("Staked voting is not enabled")]
+    StakingVotingNotEnabled,
+    #[msg("Stake account passed more than once")]
+    DuplicateStakeAccount,
+    #[msg("Stake account does not belong to the voter and staking pool")]
+    InvalidStakeAccount,
+    #[msg("Stake is not eligible to vote on this proposal")]
+    StakeNotEligible,
+    #[msg("Only treasury proposals can spend from the treasury")]
+    NotTreasuryProposal,
+    #[msg("Invalid spend limit")]
+    InvalidSpendLimit,
+    #[msg("Treasury spend limit exceeded")]
+    SpendLimitExceeded,
+    #[msg("Treasury transfer accounts are required")]
+    TreasuryAccountsRequired,
+    #[msg("Recipient does not match the treasury transfer")]
+    InvalidTreasuryRecipient,
+    #[msg("Invalid treasury token account")]
+    InvalidTreasuryAccount,
+    #[msg("Invalid proposal option")]
+    InvalidOption,
+    #[msg("Invalid outcome rule")]
+    InvalidOutcomeRule,
+    #[msg("Invalid delegate")]
+    InvalidDelegate,
+    #[msg("Votes are delegated")]
+    VotesDelegated,
+    #[msg("Delegate override account is required")]
+    DelegateOverrideRequired,
+    #[msg("Delegation and delegate record are required while votes are delegated")]
+    DelegationAccountsRequired,
+    #[msg("Invalid proposal types")]
+    InvalidProposalTypes,
+    #[msg("Identity attestation is required for quadratic votes")]
+    IdentityAttestationRequired,
+    #[msg("Unauthorized")]
+    Unauthorized,
+    #[msg("Invalid council configuration")]
+    InvalidCouncil,
+    #[msg("Signer is not a council member")]
+    NotCouncilMember,
+    #[msg("Council member already approved the veto")]
+    AlreadyApprovedVeto,
+    #[msg("Veto window has closed")]
+    VetoWindowClosed,
+    #[msg("Staking program account is required to vote with stakes")]
+    StakingProgramRequired,
+    #[msg("Stake already backs another voter on this proposal")]
+    StakeAlreadyVoted,
+    #[msg("Vote escrow does not belong to the voter in this staking pool")]
+    InvalidVoteEscrow,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proposal(option_count: usize, vote_mode: VoteMode) -> Proposal {
+        Proposal {
+            governance: Pubkey::default(),
+            proposer: Pubkey::default(),
+            title: String::new(),
+            description: String::new(),
+            proposal_type: ProposalType::Community,
+            votes_for: 0,
+            votes_against: 0,
+            votes_abstain: 0,
+            total_votes: 0,
+            total_voting_power: 0,
+            quadratic: false,
+            start_time: 0,
+            end_time: 0,
+            execution_time: 0,
+            status: ProposalStatus::Active,
+            quorum_reached: false,
+            transaction_count: 0,
+            executed_transaction_count: 0,
+            supply_snapshot: 0,
+            treasury_transfer: None,
+            treasury_transfer_executed: false,
+            options: (0..option_count)
+                .map(|index| ProposalOption {
+                    label: index.to_string(),
+                    votes: 0,
+                    transaction_count: 0,
+                })
+                .collect(),
+            vote_mode,
+            outcome_rule: OutcomeRule::Plurality,
+            winning_option: None,
+            execution_option: 0,
+            veto_approvals: 0,
+            veto_council_version: 0,
+            votes_cast: 0,
+        }
+    }
+
+    fn option_vote_record(option_votes: &[(u8, u64)]) -> VoteRecord {
+        VoteRecord {
+            voter: Pubkey::default(),
+            proposal: Pubkey::default(),
+            choice: None,
+            voting_power: option_votes.iter().map(|(_, voting_power)| voting_power).sum(),
+            staked_power: 0,
+            delegated_power: 0,
+            has_voted: true,
+            relinquished: false,
+            option_votes: option_votes
+                .iter()
+                .map(|&(option_index, voting_power)| OptionVote {
+                    option_index,
+                    voting_power,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn ranked_votes_score_by_position() {
+        let mut proposal = proposal(4, VoteMode::Ranked);
+        let mut vote_record = option_vote_record(&[(2, 100), (0, 100), (3, 100)]);
+        vote_record.voting_power = 100;
+
+        add_vote_tally(&mut proposal, &vote_record);
+        let votes: Vec<u64> = proposal.options.iter().map(|option| option.votes).collect();
+        assert_eq!(votes, vec![75, 0, 100, 50]);
+        assert_eq!(proposal.total_votes, 100);
+        assert_eq!(proposal.leading_option(), Some(2));
+
+        remove_vote_tally(&mut proposal, &vote_record);
+        assert!(proposal.options.iter().all(|option| option.votes == 0));
+        assert_eq!(proposal.total_votes, 0);
+    }
+
+    #[test]
+    fn withdrawn_and_zero_power_votes_still_count_as_cast() {
+        let mut proposal = proposal(2, VoteMode::SingleChoice);
+        let vote_record = option_vote_record(&[(0, 0)]);
+
+        add_vote_tally(&mut proposal, &vote_record);
+        assert_eq!(proposal.total_votes, 0);
+        assert_eq!(proposal.votes_cast, 1);
+
+        remove_vote_tally(&mut proposal, &vote_record);
+        assert_eq!(proposal.votes_cast, 1);
+    }
+
+    #[test]
+    fn execution_uses_winning_option_transactions() {
+        let mut proposal = proposal(3, VoteMode::SingleChoice);
+        proposal.options[0].transaction_count = 4;
+        proposal.options[2].transaction_count = 1;
+
+        proposal.execution_option = 2;
+        assert_eq!(proposal.executable_transaction_count(), 1);
+        // Per-option counts are left alone, so appending stays consistent
+        assert_eq!(proposal.next_transaction_index(0), 4);
+        assert_eq!(proposal.transaction_count, 0);
+    }
+
+    #[test]
+    fn reduce_vote_power_takes_split_votes_in_order() {
+        let mut vote_record = option_vote_record(&[(1, 30), (0, 50), (2, 20)]);
+        vote_record.delegated_power = 60;
+
+        reduce_vote_power(&mut vote_record, 60, VoteMode::WeightedSplit);
+        let remaining: Vec<u64> = vote_record
+            .option_votes
+            .iter()
+            .map(|option_vote| option_vote.voting_power)
+            .collect();
+        assert_eq!(remaining, vec![0, 20, 20]);
+        assert_eq!(vote_record.voting_power, 40);
+        assert_eq!(vote_record.delegated_power, 0);
+    }
+
+    #[test]
+    fn reduce_vote_power_lowers_every_approved_option() {
+        let mut vote_record = option_vote_record(&[(0, 100), (2, 100)]);
+        vote_record.voting_power = 100;
+        vote_record.delegated_power = 70;
+
+        reduce_vote_power(&mut vote_record, 70, VoteMode::Approval);
+        assert!(vote_record
+            .option_votes
+            .iter()
+            .all(|option_vote| option_vote.voting_power == 30));
+        assert_eq!(vote_record.voting_power, 30);
+        assert_eq!(vote_record.delegated_power, 0);
+    }
+
+    #[test]
+    fn overridden_power_leaves_the_delegate_tally() {
+        let mut proposal = proposal(3, VoteMode::WeightedSplit);
+        let mut vote_record = option_vote_record(&[(0, 80), (1, 40)]);
+        vote_record.delegated_power = 100;
+        add_vote_tally(&mut proposal, &vote_record);
+
+        remove_vote_tally(&mut proposal, &vote_record);
+        reduce_vote_power(&mut vote_record, 100, proposal.vote_mode);
+        add_vote_tally(&mut proposal, &vote_record);
+
+        assert_eq!(proposal.options[0].votes, 0);
+        assert_eq!(proposal.options[1].votes, 20);
+        assert_eq!(proposal.total_votes, 20);
+        assert_eq!(proposal.total_voting_power, 20);
+    }
+
+    #[test]
+    fn quadratic_weighted_split_counts_one_root() {
+        let mut proposal = proposal(3, VoteMode::WeightedSplit);
+        proposal.quadratic = true;
+        let vote_record = option_vote_record(&[(0, 75), (1, 25)]);
+
+        add_vote_tally(&mut proposal, &vote_record);
+        assert_eq!(proposal.options[0].votes, 7);
+        assert_eq!(proposal.options[1].votes, 2);
+        assert_eq!(proposal.total_votes, 10);
+        assert_eq!(proposal.total_voting_power, 100);
+
+        // Splitting can't beat putting the whole vote on one option
+        let mut single = self::proposal(3, VoteMode::WeightedSplit);
+        single.quadratic = true;
+        add_vote_tally(&mut single, &option_vote_record(&[(0, 100)]));
+        assert_eq!(single.total_votes, proposal.total_votes);
+
+        remove_vote_tally(&mut proposal, &vote_record);
+        assert!(proposal.options.iter().all(|option| option.votes == 0));
+        assert_eq!(proposal.total_votes, 0);
+    }
+
+    #[test]
+    fn quadratic_delegated_power_is_rooted_with_the_delegate_vote() {
+        let mut proposal = proposal(0, VoteMode::SingleChoice);
+        proposal.quadratic = true;
+        let mut vote_record = option_vote_record(&[]);
+        vote_record.choice = Some(VoteChoice::For);
+        vote_record.voting_power = 900;
+        vote_record.delegated_power = 800;
+
+        add_vote_tally(&mut proposal, &vote_record);
+        assert_eq!(proposal.votes_for, 30);
+        assert_eq!(proposal.total_votes, 30);
+
+        // A delegator of 400 overriding leaves sqrt(500)
+        remove_vote_tally(&mut proposal, &vote_record);
+        reduce_vote_power(&mut vote_record, 400, proposal.vote_mode);
+        add_vote_tally(&mut proposal, &vote_record);
+        assert_eq!(proposal.votes_for, 22);
+        assert_eq!(proposal.total_votes, 22);
+        assert_eq!(proposal.total_voting_power, 500);
+    }
+
+    fn council(member_count: u8, threshold: u8, version: u32) -> Council {
+        Council {
+            members: (0..member_count)
+                .map(|seed| Pubkey::new_from_array([seed + 1; 32]))
+                .collect(),
+            threshold,
+            version,
+        }
+    }
+
+    #[test]
+    fn veto_needs_threshold_of_distinct_members() {
+        let council = council(3, 2, 0);
+        let mut proposal = proposal(0, VoteMode::SingleChoice);
+        proposal.status = ProposalStatus::Passed;
+
+        proposal.approve_veto(&council, &council.members[2]).unwrap();
+        assert_eq!(proposal.veto_approvals, 0b100);
+        assert!(proposal.status == ProposalStatus::Passed);
+
+        assert_eq!(
+            proposal.approve_veto(&council, &council.members[2]),
+            Err(ErrorCode::AlreadyApprovedVeto.into())
+        );
+        assert_eq!(
+            proposal.approve_veto(&council, &Pubkey::new_unique()),
+            Err(ErrorCode::NotCouncilMember.into())
+        );
+
+        proposal.approve_veto(&council, &council.members[0]).unwrap();
+        assert_eq!(proposal.veto_approvals, 0b101);
+        assert!(proposal.status == ProposalStatus::Vetoed);
+    }
+
+    #[test]
+    fn veto_approvals_reset_when_council_changes() {
+        let old_council = council(3, 2, 0);
+        let mut proposal = proposal(0, VoteMode::SingleChoice);
+        proposal.status = ProposalStatus::Passed;
+        proposal.approve_veto(&old_council, &old_council.members[0]).unwrap();
+
+        // Member 0 is gone and bit 0 now belongs to someone else
+        let mut new_council = council(3, 2, 1);
+        new_council.members.remove(0);
+        proposal.approve_veto(&new_council, &new_council.members[1]).unwrap();
+
+        assert_eq!(proposal.veto_approvals, 0b10);
+        assert_eq!(proposal.veto_council_version, 1);
+        assert!(proposal.status == ProposalStatus::Passed);
+    }
+
+    #[test]
+    fn isqrt_rounds_down() {
+        for value in 0..10_000u64 {
+            let root = isqrt(value);
+            assert!(root * root <= value && (root + 1) * (root + 1) > value);
+        }
+        assert_eq!(isqrt(u64::MAX), u32::MAX as u64);
+        assert_eq!(isqrt(1_000_000_000_000), 1_000_000);
+    }
 }
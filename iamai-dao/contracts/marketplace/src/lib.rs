@@ -1,5 +1,6 @@
 use anchor_lang::prelude::*;
 use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use iamai_staking::program::IamaiStaking;
 
 declare_id!("CDg2vpzshYKscaXa42PvP4PCKShWj6etDoyda86Fz47y");
 
@@ -20,6 +21,23 @@ pub mod iamai_marketplace {
         marketplace.total_sales = 0;
         marketplace.total_volume = 0;
         marketplace.is_initialized = true;
+        marketplace.staking_pool = Pubkey::default();
+        marketplace.staking_reward_vault = Pubkey::default();
+        marketplace.staking_share_bps = 0;
+        Ok(())
+    }
+
+    pub fn configure_staking_revenue_share(
+        ctx: Context<ConfigureStakingRevenueShare>,
+        staking_share_bps: u16, // basis points of each royalty
+    ) -> Result<()> {
+        let marketplace = &mut ctx.accounts.marketplace;
+
+        require!(staking_share_bps <= 10000, ErrorCode::InvalidRevenueShare);
+
+        marketplace.staking_pool = ctx.accounts.staking_pool.key();
+        marketplace.staking_reward_vault = ctx.accounts.staking_reward_vault.key();
+        marketplace.staking_share_bps = staking_share_bps;
         Ok(())
     }
 
@@ -72,9 +90,40 @@ pub mod iamai_marketplace {
         // Calculate royalty
         let royalty_amount = (price * marketplace.royalty_percentage as u64) / 10000;
         let creator_amount = price - royalty_amount;
+        let staking_amount = (royalty_amount * marketplace.staking_share_bps as u64) / 10000;
+        let treasury_amount = royalty_amount - staking_amount;
+
+        // Deposit stakers' share of the royalty into the staking reward vault
+        if staking_amount > 0 {
+            let staking_pool = ctx
+                .accounts
+                .staking_pool
+                .as_ref()
+                .ok_or(ErrorCode::StakingAccountsRequired)?;
+            let staking_reward_vault = ctx
+                .accounts
+                .staking_reward_vault
+                .as_ref()
+                .ok_or(ErrorCode::StakingAccountsRequired)?;
+            let staking_program = ctx
+                .accounts
+                .staking_program
+                .as_ref()
+                .ok_or(ErrorCode::StakingAccountsRequired)?;
+
+            let cpi_accounts = iamai_staking::cpi::accounts::DepositRewards {
+                staking_pool: staking_pool.to_account_info(),
+                reward_vault: staking_reward_vault.to_account_info(),
+                depositor_token_account: ctx.accounts.buyer_token_account.to_account_info(),
+                depositor: ctx.accounts.buyer.to_account_info(),
+                token_program: ctx.accounts.token_program.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new(staking_program.to_account_info(), cpi_accounts);
+            iamai_staking::cpi::deposit_rewards(cpi_ctx, staking_amount)?;
+        }
 
         // Transfer royalty to treasury
-        if royalty_amount > 0 {
+        if treasury_amount > 0 {
             let cpi_accounts = Transfer {
                 from: ctx.accounts.buyer_token_account.to_account_info(),
                 to: ctx.accounts.treasury.to_account_info(),
@@ -82,7 +131,7 @@ pub mod iamai_marketplace {
             };
             let cpi_program = ctx.accounts.token_program.to_account_info();
             let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-            token::transfer(cpi_ctx, royalty_amount)?;
+            token::transfer(cpi_ctx, treasury_amount)?;
         }
 
         // Transfer payment to creator
@@ -120,7 +169,7 @@ pub mod iamai_marketplace {
         let purchase_record = &ctx.accounts.purchase_record;
         let model_review = &mut ctx.accounts.model_review;
 
-        require!(rating >= 1 && rating <= 5, ErrorCode::InvalidRating);
+        require!((1..=5).contains(&rating), ErrorCode::InvalidRating);
         require!(purchase_record.has_access, ErrorCode::NoAccessToModel);
 
         let clock = Clock::get()?;
@@ -181,6 +230,26 @@ pub struct InitializeMarketplace<'info> {
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct ConfigureStakingRevenueShare<'info> {
+    #[account(mut, has_one = authority)]
+    pub marketplace: Account<'info, Marketplace>,
+
+    pub staking_pool: Account<'info, iamai_staking::StakingPool>,
+
+    #[account(
+        token::mint = marketplace.token_mint,
+        constraint = staking_pool
+            .reward_tokens
+            .iter()
+            .any(|reward_token| reward_token.vault == staking_reward_vault.key())
+            @ ErrorCode::InvalidStakingRewardVault,
+    )]
+    pub staking_reward_vault: Account<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+}
+
 #[derive(Accounts)]
 pub struct ListModel<'info> {
     #[account(mut)]
@@ -224,6 +293,20 @@ pub struct PurchaseModel<'info> {
     
     #[account(mut)]
     pub treasury: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        address = marketplace.staking_pool @ ErrorCode::StakingAccountsRequired,
+    )]
+    pub staking_pool: Option<Account<'info, iamai_staking::StakingPool>>,
+
+    #[account(
+        mut,
+        address = marketplace.staking_reward_vault @ ErrorCode::StakingAccountsRequired,
+    )]
+    pub staking_reward_vault: Option<Account<'info, TokenAccount>>,
+
+    pub staking_program: Option<Program<'info, IamaiStaking>>,
     
     #[account(mut)]
     pub buyer: Signer<'info>,
@@ -289,6 +372,9 @@ pub struct Marketplace {
     pub total_sales: u64,
     pub total_volume: u64,
     pub is_initialized: bool,
+    pub staking_pool: Pubkey,
+    pub staking_reward_vault: Pubkey,
+    pub staking_share_bps: u16, // basis points of each royalty
 }
 
 #[account]
@@ -355,4 +441,10 @@ pub enum ErrorCode {
     Unauthorized,
     #[msg("Insufficient funds")]
     InsufficientFunds,
+    #[msg("Invalid revenue share")]
+    InvalidRevenueShare,
+    #[msg("Staking reward vault is not registered on the staking pool")]
+    InvalidStakingRewardVault,
+    #[msg("Staking accounts required for revenue share")]
+    StakingAccountsRequired,
 }
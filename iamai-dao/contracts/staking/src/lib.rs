@@ -1,8 +1,51 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token::spl_token::instruction::AuthorityType;
+use anchor_spl::token::{self, Burn, Mint, MintTo, SetAuthority, Token, TokenAccount, Transfer};
 
 declare_id!("Fa3w7XNsTzTqrJY1mUZ8QaorpDgMXqTWkYdtFn6GxjdP");
 
+// Virtual shares/assets added to both sides of the receipt exchange rate so a
+// donation to an empty liquid vault cannot inflate the rate against depositors.
+const LIQUID_VIRTUAL_OFFSET: u128 = 1_000_000;
+
+// Fixed-point scale for the per-share reward accumulator.
+const ACC_PRECISION: u128 = 1_000_000_000_000;
+
+// Vote-escrow lock bounds; voting power decays linearly to zero at unlock.
+pub const MIN_LOCK_DURATION: i64 = 7 * 24 * 60 * 60;
+pub const MAX_LOCK_DURATION: i64 = 4 * 365 * 24 * 60 * 60;
+
+// Oldest vote-escrow checkpoints are dropped once this many are stored.
+const MAX_ESCROW_CHECKPOINTS: usize = 32;
+
+// Additional reward mints a pool can distribute alongside IAMAI.
+pub const MAX_REWARD_TOKENS: usize = 4;
+
+// Reward boosts: at most MAX_BOOST_SOURCES sources, together adding at most
+// MAX_BOOST_BPS on top of the 1x base weight.
+pub const MAX_BOOST_SOURCES: usize = 4;
+pub const MAX_BOOST_BPS: u16 = 10000;
+
+const TOKEN_METADATA_PROGRAM_ID: Pubkey = pubkey!("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+
+// Bounds enforced on parameter updates, including those made by governance.
+pub const MAX_EARLY_UNSTAKE_PENALTY_BPS: u16 = 5000;
+pub const MAX_APY_BASIS_POINTS: u16 = 10000;
+pub const MAX_UNBONDING_PERIOD: i64 = 90 * 24 * 60 * 60;
+
+// APY changes remembered per tier; older periods are merged conservatively.
+const MAX_APY_CHANGES: usize = 16;
+
+// Shortest reward epoch a pool can be configured with.
+pub const MIN_EPOCH_DURATION: i64 = 60 * 60;
+
+// Largest share of a referee's rewards a referrer can earn on top.
+pub const MAX_REFERRAL_BPS: u16 = 2000;
+
+// Epochs a referrer's stake must have been held before it can refer others.
+pub const MIN_REFERRER_STAKE_EPOCHS: u64 = 3;
+
 #[program]
 pub mod iamai_staking {
     use super::*;
@@ -19,6 +62,427 @@ pub mod iamai_staking {
         staking_pool.total_staked = 0;
         staking_pool.total_rewards_distributed = 0;
         staking_pool.is_initialized = true;
+        staking_pool.liquid_tier = Pubkey::default();
+        staking_pool.receipt_mint = Pubkey::default();
+        staking_pool.liquid_vault = Pubkey::default();
+        staking_pool.liquid_last_accrual = 0;
+        staking_pool.liquid_staked = 0;
+        staking_pool.unstake_mode = UnstakeMode::Penalty;
+        staking_pool.unbonding_period = 0;
+        staking_pool.penalty_destination = PenaltyDestination::Burn;
+        staking_pool.penalty_treasury = Pubkey::default();
+        staking_pool.total_penalties_collected = 0;
+        staking_pool.acc_reward_per_share = 0;
+        staking_pool.total_unbonding = 0;
+        staking_pool.accumulated_rewards_outstanding = 0;
+        staking_pool.total_escrowed = 0;
+        staking_pool.reward_tokens = Vec::new();
+        staking_pool.is_paused = false;
+        staking_pool.guardian = Pubkey::default();
+        staking_pool.boost_sources = Vec::new();
+        staking_pool.total_effective_stake = 0;
+        staking_pool.governance_authority = Pubkey::default();
+        staking_pool.reward_mode = RewardMode::Continuous;
+        staking_pool.epoch_duration = 0;
+        staking_pool.epoch_reward_budget = 0;
+        staking_pool.current_reward_epoch = 0;
+        staking_pool.epoch_start_time = 0;
+        staking_pool.epoch_rewards_outstanding = 0;
+        staking_pool.epoch_eligible_stake = 0;
+        staking_pool.referral_bps = 0;
+        staking_pool.referral_rewards_outstanding = 0;
+        Ok(())
+    }
+
+    pub fn configure_penalty_policy(
+        ctx: Context<ConfigurePenaltyPolicy>,
+        penalty_destination: PenaltyDestination,
+    ) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+
+        staking_pool.penalty_treasury = match penalty_destination {
+            PenaltyDestination::Treasury => ctx
+                .accounts
+                .penalty_treasury
+                .as_ref()
+                .ok_or(ErrorCode::PenaltyTreasuryRequired)?
+                .key(),
+            _ => Pubkey::default(),
+        };
+        staking_pool.penalty_destination = penalty_destination;
+        Ok(())
+    }
+
+    pub fn configure_unbonding(
+        ctx: Context<ConfigureUnbonding>,
+        unstake_mode: UnstakeMode,
+        unbonding_period: i64, // seconds
+    ) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+
+        if unstake_mode != UnstakeMode::Penalty {
+            require!(unbonding_period > 0, ErrorCode::InvalidUnbondingPeriod);
+        }
+
+        staking_pool.unstake_mode = unstake_mode;
+        staking_pool.unbonding_period = unbonding_period;
+        Ok(())
+    }
+
+    // Switches the pool to epoch rewards: tier APY stops accruing and each
+    // epoch's budget is split across stakes by their whole-epoch balance.
+    pub fn configure_epoch_rewards(
+        ctx: Context<ConfigureEpochRewards>,
+        epoch_duration: i64, // seconds
+        epoch_reward_budget: u64,
+    ) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+
+        require!(
+            epoch_duration >= MIN_EPOCH_DURATION,
+            ErrorCode::InvalidEpochDuration
+        );
+
+        // Existing stakes accrued under APY, so the mode only changes on an empty pool
+        if staking_pool.reward_mode != RewardMode::Epoch {
+            require!(
+                staking_pool.vault_staked() == 0 && staking_pool.total_unbonding == 0,
+                ErrorCode::PoolNotEmpty
+            );
+            let clock = Clock::get()?;
+            staking_pool.reward_mode = RewardMode::Epoch;
+            staking_pool.epoch_start_time = clock.unix_timestamp;
+        }
+
+        staking_pool.epoch_duration = epoch_duration;
+        staking_pool.epoch_reward_budget = epoch_reward_budget;
+        Ok(())
+    }
+
+    // Permissionless crank closing the current reward epoch.
+    pub fn advance_epoch(ctx: Context<AdvanceEpoch>) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let epoch_record = &mut ctx.accounts.epoch_record;
+
+        require!(
+            staking_pool.reward_mode == RewardMode::Epoch,
+            ErrorCode::EpochRewardsNotEnabled
+        );
+
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+        require!(
+            current_time >= staking_pool.epoch_start_time + staking_pool.epoch_duration,
+            ErrorCode::EpochNotComplete
+        );
+
+        // Nothing is paid for an epoch nobody staked through, or while paused
+        let reward_budget = if staking_pool.epoch_eligible_stake == 0 || staking_pool.is_paused {
+            0
+        } else {
+            std::cmp::min(
+                staking_pool.epoch_reward_budget,
+                reward_reserve(staking_pool, &ctx.accounts.vault),
+            )
+        };
+
+        epoch_record.pool = staking_pool.key();
+        epoch_record.epoch = staking_pool.current_reward_epoch;
+        epoch_record.start_time = staking_pool.epoch_start_time;
+        epoch_record.end_time = current_time;
+        epoch_record.total_staked = staking_pool.vault_staked();
+        epoch_record.eligible_stake = staking_pool.epoch_eligible_stake;
+        epoch_record.reward_budget = reward_budget;
+        epoch_record.rewards_claimed = 0;
+
+        staking_pool.epoch_rewards_outstanding += reward_budget;
+        staking_pool.current_reward_epoch += 1;
+        staking_pool.epoch_start_time = current_time;
+        // Every stake open now holds its full amount from the start of the next epoch
+        staking_pool.epoch_eligible_stake = staking_pool.vault_staked();
+        Ok(())
+    }
+
+    // Claims the oldest unclaimed epoch; epochs are claimed strictly in order.
+    pub fn claim_epoch_rewards(ctx: Context<ClaimEpochRewards>) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let epoch_record = &mut ctx.accounts.epoch_record;
+        let user_stake = &mut ctx.accounts.user_stake;
+
+        verify_stake_owner(
+            user_stake,
+            &ctx.accounts.user.key(),
+            ctx.accounts.position_token_account.as_deref(),
+        )?;
+        require!(user_stake.is_active, ErrorCode::StakeNotActive);
+        require!(!user_stake.is_unbonding, ErrorCode::StakeUnbonding);
+
+        let rewards = epoch_record.share_of(user_stake.epoch_weight(epoch_record.epoch));
+
+        user_stake.next_claim_epoch = epoch_record.epoch + 1;
+        epoch_record.rewards_claimed += rewards;
+        staking_pool.epoch_rewards_outstanding -= rewards;
+        staking_pool.total_rewards_distributed += rewards;
+        accrue_referral_reward(user_stake, staking_pool, rewards);
+
+        transfer_from_vault(
+            &ctx.accounts.vault,
+            &ctx.accounts.user_token_account,
+            &ctx.accounts.token_program,
+            staking_pool.key(),
+            ctx.bumps.vault,
+            rewards,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn set_guardian(
+        ctx: Context<SetGuardian>,
+        guardian: Pubkey,
+    ) -> Result<()> {
+        ctx.accounts.staking_pool.guardian = guardian;
+        Ok(())
+    }
+
+    pub fn pause_pool(ctx: Context<SetPoolPaused>) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        require!(!staking_pool.is_paused, ErrorCode::PoolPaused);
+        staking_pool.is_paused = true;
+        Ok(())
+    }
+
+    pub fn unpause_pool(ctx: Context<SetPoolPaused>) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        require!(staking_pool.is_paused, ErrorCode::PoolNotPaused);
+        staking_pool.is_paused = false;
+        Ok(())
+    }
+
+    // remaining_accounts carry the stake's unclaimed EpochRecords, in order
+    // from next_claim_epoch, followed by any boost accounts. The shares of the
+    // epochs passed are forfeited back to the reward reserve.
+    pub fn emergency_withdraw<'info>(
+        ctx: Context<'_, '_, 'info, 'info, EmergencyWithdraw<'info>>,
+    ) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let staking_tier = &mut ctx.accounts.staking_tier;
+        let user_stake = &mut ctx.accounts.user_stake;
+
+        verify_stake_owner(
+            user_stake,
+            &ctx.accounts.user.key(),
+            ctx.accounts.position_token_account.as_deref(),
+        )?;
+        check_emergency_exit(staking_pool, user_stake)?;
+
+        let clock = Clock::get()?;
+
+        // Release delegated voting power so the stake can close
+        if user_stake.delegate != Pubkey::default() {
+            let delegate_record = ctx
+                .accounts
+                .delegate_record
+                .as_mut()
+                .ok_or(ErrorCode::InvalidDelegate)?;
+            delegate_record.remove_power(user_stake.amount);
+            user_stake.delegate = Pubkey::default();
+        }
+
+        let epoch_record_count = ctx
+            .remaining_accounts
+            .iter()
+            .take_while(|account_info| account_info.owner == &crate::ID)
+            .count();
+        let (epoch_record_infos, boost_accounts) =
+            ctx.remaining_accounts.split_at(epoch_record_count);
+        for epoch_record_info in epoch_record_infos {
+            require!(epoch_record_info.is_writable, ErrorCode::InvalidEpochRecord);
+            let mut epoch_record = Account::<EpochRecord>::try_from(epoch_record_info)?;
+            require_keys_eq!(
+                epoch_record.pool,
+                staking_pool.key(),
+                ErrorCode::InvalidEpochRecord
+            );
+            release_epoch_share(user_stake, staking_pool, &mut epoch_record)?;
+            epoch_record.exit(&crate::ID)?;
+        }
+
+        // The stake no longer counts towards the epoch in progress
+        if staking_pool.reward_mode == RewardMode::Epoch && !user_stake.is_unbonding {
+            lower_epoch_weight(user_stake, staking_pool, 0);
+        }
+
+        // Extra reward tokens stay claimable; IAMAI rewards are forfeited
+        settle_with_verified_boost(
+            user_stake,
+            staking_pool,
+            &ctx.accounts.user.key(),
+            boost_accounts,
+            clock.epoch,
+        )?;
+        staking_pool.accumulated_rewards_outstanding = staking_pool
+            .accumulated_rewards_outstanding
+            .saturating_sub(user_stake.accumulated_rewards);
+        user_stake.accumulated_rewards = 0;
+
+        if user_stake.is_unbonding {
+            staking_pool.total_unbonding -= user_stake.amount;
+        } else {
+            staking_pool.total_staked -= user_stake.amount;
+            staking_tier.total_staked -= user_stake.amount;
+        }
+
+        user_stake.is_active = false;
+        user_stake.is_unbonding = false;
+        update_reward_weight(user_stake, staking_pool);
+
+        transfer_from_vault(
+            &ctx.accounts.vault,
+            &ctx.accounts.user_token_account,
+            &ctx.accounts.token_program,
+            staking_pool.key(),
+            ctx.bumps.vault,
+            user_stake.amount,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn set_boost_sources(
+        ctx: Context<SetBoostSources>,
+        boost_sources: Vec<BoostSource>,
+    ) -> Result<()> {
+        require!(
+            boost_sources.len() <= MAX_BOOST_SOURCES,
+            ErrorCode::InvalidBoostSources
+        );
+        let total_boost_bps: u32 = boost_sources
+            .iter()
+            .map(|boost_source| boost_source.multiplier_bps as u32)
+            .sum();
+        require!(
+            total_boost_bps <= MAX_BOOST_BPS as u32,
+            ErrorCode::InvalidBoostSources
+        );
+
+        ctx.accounts.staking_pool.boost_sources = boost_sources;
+        Ok(())
+    }
+
+    pub fn set_referral_bps(
+        ctx: Context<SetReferralBps>,
+        referral_bps: u16,
+    ) -> Result<()> {
+        require!(
+            referral_bps <= MAX_REFERRAL_BPS,
+            ErrorCode::ParameterOutOfBounds
+        );
+        ctx.accounts.staking_pool.referral_bps = referral_bps;
+        Ok(())
+    }
+
+    pub fn set_governance_authority(
+        ctx: Context<SetGovernanceAuthority>,
+        governance_authority: Pubkey,
+    ) -> Result<()> {
+        ctx.accounts.staking_pool.governance_authority = governance_authority;
+        Ok(())
+    }
+
+    // Tier accounts whose APY changes are passed in remaining_accounts, in the
+    // same order as `params.tier_apy_basis_points`.
+    pub fn update_pool_params<'info>(
+        ctx: Context<'_, '_, 'info, 'info, UpdatePoolParams<'info>>,
+        params: PoolParams,
+    ) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+
+        if let Some(early_unstake_penalty) = params.early_unstake_penalty {
+            require!(
+                early_unstake_penalty <= MAX_EARLY_UNSTAKE_PENALTY_BPS,
+                ErrorCode::ParameterOutOfBounds
+            );
+            staking_pool.early_unstake_penalty = early_unstake_penalty;
+        }
+
+        if let Some(unbonding_period) = params.unbonding_period {
+            require!(
+                unbonding_period <= MAX_UNBONDING_PERIOD
+                    && (unbonding_period > 0 || staking_pool.unstake_mode == UnstakeMode::Penalty),
+                ErrorCode::ParameterOutOfBounds
+            );
+            staking_pool.unbonding_period = unbonding_period;
+        }
+
+        // An epoch budget cannot promise more than the reward reserve holds
+        if let Some(epoch_reward_budget) = params.epoch_reward_budget {
+            require!(
+                staking_pool.reward_mode == RewardMode::Epoch,
+                ErrorCode::EpochRewardsNotEnabled
+            );
+            let vault = ctx.accounts.vault.as_ref().ok_or(ErrorCode::VaultRequired)?;
+            require!(
+                epoch_reward_budget <= reward_reserve(staking_pool, vault),
+                ErrorCode::ParameterOutOfBounds
+            );
+            staking_pool.epoch_reward_budget = epoch_reward_budget;
+        }
+
+        if let Some(referral_bps) = params.referral_bps {
+            require!(
+                referral_bps <= MAX_REFERRAL_BPS,
+                ErrorCode::ParameterOutOfBounds
+            );
+            staking_pool.referral_bps = referral_bps;
+        }
+
+        if let Some(boost_multipliers) = params.boost_multipliers {
+            require!(
+                boost_multipliers.len() == staking_pool.boost_sources.len(),
+                ErrorCode::InvalidBoostSources
+            );
+            let total_boost_bps: u32 = boost_multipliers
+                .iter()
+                .map(|multiplier_bps| *multiplier_bps as u32)
+                .sum();
+            require!(
+                total_boost_bps <= MAX_BOOST_BPS as u32,
+                ErrorCode::ParameterOutOfBounds
+            );
+            for (boost_source, multiplier_bps) in
+                staking_pool.boost_sources.iter_mut().zip(boost_multipliers)
+            {
+                boost_source.multiplier_bps = multiplier_bps;
+            }
+        }
+
+        require!(
+            params.tier_apy_basis_points.len() == ctx.remaining_accounts.len(),
+            ErrorCode::InvalidStakingTier
+        );
+        let current_time = Clock::get()?.unix_timestamp;
+        for (tier_info, apy_basis_points) in ctx
+            .remaining_accounts
+            .iter()
+            .zip(params.tier_apy_basis_points)
+        {
+            require!(
+                apy_basis_points <= MAX_APY_BASIS_POINTS,
+                ErrorCode::ParameterOutOfBounds
+            );
+            require!(tier_info.is_writable, ErrorCode::InvalidStakingTier);
+
+            let mut staking_tier = Account::<StakingTier>::try_from(tier_info)?;
+            require_keys_eq!(
+                staking_tier.pool,
+                staking_pool.key(),
+                ErrorCode::InvalidStakingTier
+            );
+            staking_tier.set_apy(apy_basis_points, current_time);
+            staking_tier.exit(&crate::ID)?;
+        }
+
         Ok(())
     }
 
@@ -27,41 +491,53 @@ pub mod iamai_staking {
         duration_days: u32,
         apy_basis_points: u16,
     ) -> Result<()> {
+        require!(
+            apy_basis_points <= MAX_APY_BASIS_POINTS,
+            ErrorCode::ParameterOutOfBounds
+        );
+
         let staking_tier = &mut ctx.accounts.staking_tier;
         staking_tier.pool = ctx.accounts.staking_pool.key();
         staking_tier.duration_days = duration_days;
         staking_tier.apy_basis_points = apy_basis_points;
         staking_tier.total_staked = 0;
         staking_tier.is_active = true;
+        staking_tier.apy_history = Vec::new();
         Ok(())
     }
 
     pub fn stake_tokens(
         ctx: Context<StakeTokens>,
         amount: u64,
-        tier_index: u8,
+        _tier_index: u8,
     ) -> Result<()> {
         let staking_pool = &mut ctx.accounts.staking_pool;
         let staking_tier = &mut ctx.accounts.staking_tier;
         let user_stake = &mut ctx.accounts.user_stake;
+        let user_key = ctx.accounts.user.key();
 
-        let clock = Clock::get()?;
-        let start_time = clock.unix_timestamp;
-        let end_time = start_time + (staking_tier.duration_days as i64 * 24 * 60 * 60);
-
-        // Initialize user stake
-        user_stake.user = ctx.accounts.user.key();
-        user_stake.pool = staking_pool.key();
-        user_stake.tier = staking_tier.key();
-        user_stake.amount = amount;
-        user_stake.start_time = start_time;
-        user_stake.end_time = end_time;
-        user_stake.rewards_claimed = 0;
-        user_stake.is_active = true;
+        open_stake(
+            user_stake,
+            staking_pool,
+            staking_tier,
+            user_key,
+            amount,
+            false,
+        )?;
 
-        // Update pool and tier totals
-        staking_pool.total_staked += amount;
-        staking_tier.total_staked += amount;
+        // Record who referred this staker; the stake account is only created once
+        match (&ctx.accounts.referrer_stake, &mut ctx.accounts.referral) {
+            (Some(referrer_stake), Some(referral)) => {
+                validate_referrer(referrer_stake, &user_key, Clock::get()?.epoch)?;
+                referral.pool = staking_pool.key();
+                referral.referrer = referrer_stake.user;
+                referral.referee = user_key;
+                referral.total_claimed = 0;
+                user_stake.referrer = referrer_stake.user;
+            }
+            (None, None) => {}
+            _ => return err!(ErrorCode::ReferralAccountRequired),
+        }
 
         // Transfer tokens to vault
         let cpi_accounts = Transfer {
@@ -76,6 +552,71 @@ pub mod iamai_staking {
         Ok(())
     }
 
+    // Opens a stake owned by `beneficiary` and funded by the payer, e.g. for
+    // vesting contracts. A vest-locked stake cannot be unstaked early.
+    pub fn stake_for(
+        ctx: Context<StakeFor>,
+        beneficiary: Pubkey,
+        amount: u64,
+        locked_until_vest: bool,
+    ) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let staking_tier = &mut ctx.accounts.staking_tier;
+        let user_stake = &mut ctx.accounts.user_stake;
+
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(
+            beneficiary != Pubkey::default(),
+            ErrorCode::InvalidBeneficiary
+        );
+
+        open_stake(
+            user_stake,
+            staking_pool,
+            staking_tier,
+            beneficiary,
+            amount,
+            locked_until_vest,
+        )?;
+
+        // Transfer tokens to vault
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.payer_token_account.to_account_info(),
+            to: ctx.accounts.vault.to_account_info(),
+            authority: ctx.accounts.payer.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer(cpi_ctx, amount)?;
+
+        Ok(())
+    }
+
+    pub fn claim_referral_rewards(ctx: Context<ClaimReferralRewards>) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let referral = &mut ctx.accounts.referral;
+        let user_stake = &mut ctx.accounts.user_stake;
+
+        let rewards = user_stake.referral_rewards_owed;
+        require!(rewards > 0, ErrorCode::NoRewardsAvailable);
+
+        user_stake.referral_rewards_owed = 0;
+        referral.total_claimed += rewards;
+        staking_pool.referral_rewards_outstanding -= rewards;
+        staking_pool.total_rewards_distributed += rewards;
+
+        transfer_from_vault(
+            &ctx.accounts.vault,
+            &ctx.accounts.referrer_token_account,
+            &ctx.accounts.token_program,
+            staking_pool.key(),
+            ctx.bumps.vault,
+            rewards,
+        )?;
+
+        Ok(())
+    }
+
     pub fn unstake_tokens(
         ctx: Context<UnstakeTokens>,
         early_unstake: bool,
@@ -84,10 +625,27 @@ pub mod iamai_staking {
         let staking_tier = &mut ctx.accounts.staking_tier;
         let user_stake = &mut ctx.accounts.user_stake;
 
+        verify_stake_owner(
+            user_stake,
+            &ctx.accounts.user.key(),
+            ctx.accounts.position_token_account.as_deref(),
+        )?;
         require!(user_stake.is_active, ErrorCode::StakeNotActive);
+        require!(!user_stake.is_unbonding, ErrorCode::StakeUnbonding);
+        require!(
+            user_stake.delegate == Pubkey::default(),
+            ErrorCode::StakeDelegated
+        );
+
+        record_epoch_amount_change(user_stake, staking_pool, 0)?;
 
         let clock = Clock::get()?;
         let current_time = clock.unix_timestamp;
+        // Stakes backing a live governance vote stay put until voting ends
+        require!(
+            current_time > user_stake.vote_locked_until,
+            ErrorCode::StakeVoteLocked
+        );
 
         let mut amount_to_return = user_stake.amount;
         let mut penalty = 0u64;
@@ -95,22 +653,173 @@ pub mod iamai_staking {
         // Check if early unstaking
         if current_time < user_stake.end_time {
             require!(early_unstake, ErrorCode::StakingPeriodNotComplete);
-            penalty = (user_stake.amount * staking_pool.early_unstake_penalty as u64) / 10000;
+            require!(
+                user_stake.vest_unlocked_at(current_time),
+                ErrorCode::StakeVestLocked
+            );
+            require!(
+                staking_pool.unstake_mode != UnstakeMode::Cooldown,
+                ErrorCode::EarlyUnstakePenaltyDisabled
+            );
+            penalty = early_unstake_penalty(staking_pool, user_stake.amount);
             amount_to_return -= penalty;
         }
 
         // Calculate and add pending rewards
-        let rewards = calculate_rewards(user_stake, staking_tier, current_time)?;
+        settle_with_verified_boost(
+            user_stake,
+            staking_pool,
+            &ctx.accounts.user.key(),
+            ctx.remaining_accounts,
+            clock.epoch,
+        )?;
+        let apy_rewards = calculate_rewards(user_stake, staking_pool, staking_tier, current_time)?;
+        let rewards = apy_rewards + user_stake.accumulated_rewards;
         amount_to_return += rewards;
 
         // Update totals
         staking_pool.total_staked -= user_stake.amount;
         staking_tier.total_staked -= user_stake.amount;
         staking_pool.total_rewards_distributed += rewards;
+        accrue_referral_reward(user_stake, staking_pool, rewards);
+        staking_pool.accumulated_rewards_outstanding = staking_pool
+            .accumulated_rewards_outstanding
+            .saturating_sub(user_stake.accumulated_rewards);
 
         // Mark stake as inactive
         user_stake.is_active = false;
-        user_stake.rewards_claimed += rewards;
+        user_stake.rewards_claimed += apy_rewards;
+        user_stake.accumulated_rewards = 0;
+        update_reward_weight(user_stake, staking_pool);
+
+        if penalty > 0 {
+            collect_penalty(
+                staking_pool,
+                user_stake.user,
+                penalty,
+                &ctx.accounts.vault,
+                &ctx.accounts.token_mint,
+                ctx.accounts.penalty_treasury.as_ref(),
+                &ctx.accounts.token_program,
+                ctx.bumps.vault,
+            )?;
+        }
+
+        // Transfer tokens back to user
+        let staking_pool_key = staking_pool.key();
+        let seeds = &[
+            b"vault",
+            staking_pool_key.as_ref(),
+            &[ctx.bumps.vault],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.vault.to_account_info(),
+            to: ctx.accounts.user_token_account.to_account_info(),
+            authority: ctx.accounts.vault.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, amount_to_return)?;
+
+        Ok(())
+    }
+
+    pub fn request_unstake(ctx: Context<RequestUnstake>) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let staking_tier = &mut ctx.accounts.staking_tier;
+        let user_stake = &mut ctx.accounts.user_stake;
+
+        verify_stake_owner(
+            user_stake,
+            &ctx.accounts.user.key(),
+            ctx.accounts.position_token_account.as_deref(),
+        )?;
+        require!(user_stake.is_active, ErrorCode::StakeNotActive);
+        require!(!user_stake.is_unbonding, ErrorCode::StakeUnbonding);
+        require!(
+            user_stake.delegate == Pubkey::default(),
+            ErrorCode::StakeDelegated
+        );
+        require!(
+            staking_pool.unstake_mode != UnstakeMode::Penalty,
+            ErrorCode::CooldownNotEnabled
+        );
+        record_epoch_amount_change(user_stake, staking_pool, 0)?;
+
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+        // Stakes backing a live governance vote stay put until voting ends
+        require!(
+            current_time > user_stake.vote_locked_until,
+            ErrorCode::StakeVoteLocked
+        );
+
+        // Vest-locked stakes only start unbonding once the lock has ended
+        require!(
+            user_stake.vest_unlocked_at(current_time),
+            ErrorCode::StakeVestLocked
+        );
+
+        // Start unbonding; rewards stop accruing from here
+        settle_with_verified_boost(
+            user_stake,
+            staking_pool,
+            &ctx.accounts.user.key(),
+            ctx.remaining_accounts,
+            clock.epoch,
+        )?;
+        user_stake.is_unbonding = true;
+        user_stake.unbonding_start = current_time;
+        user_stake.unbonding_end = current_time + staking_pool.unbonding_period;
+        update_reward_weight(user_stake, staking_pool);
+
+        // Unbonding stake no longer counts towards totals
+        staking_pool.total_staked -= user_stake.amount;
+        staking_tier.total_staked -= user_stake.amount;
+        staking_pool.total_unbonding += user_stake.amount;
+
+        Ok(())
+    }
+
+    pub fn withdraw_unbonded(ctx: Context<WithdrawUnbonded>) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let staking_tier = &ctx.accounts.staking_tier;
+        let user_stake = &mut ctx.accounts.user_stake;
+
+        verify_stake_owner(
+            user_stake,
+            &ctx.accounts.user.key(),
+            ctx.accounts.position_token_account.as_deref(),
+        )?;
+        require!(user_stake.is_active, ErrorCode::StakeNotActive);
+        require!(user_stake.is_unbonding, ErrorCode::StakeNotUnbonding);
+
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        require!(
+            current_time >= user_stake.unbonding_end,
+            ErrorCode::UnbondingPeriodNotComplete
+        );
+
+        // Principal plus rewards accrued before unbonding started
+        let apy_rewards = calculate_rewards(user_stake, staking_pool, staking_tier, current_time)?;
+        let rewards = apy_rewards + user_stake.accumulated_rewards;
+        let amount_to_return = user_stake.amount + rewards;
+
+        staking_pool.total_unbonding -= user_stake.amount;
+        staking_pool.total_rewards_distributed += rewards;
+        accrue_referral_reward(user_stake, staking_pool, rewards);
+        staking_pool.accumulated_rewards_outstanding = staking_pool
+            .accumulated_rewards_outstanding
+            .saturating_sub(user_stake.accumulated_rewards);
+
+        user_stake.is_active = false;
+        user_stake.is_unbonding = false;
+        user_stake.rewards_claimed += apy_rewards;
+        user_stake.accumulated_rewards = 0;
 
         // Transfer tokens back to user
         let staking_pool_key = staking_pool.key();
@@ -138,17 +847,36 @@ pub mod iamai_staking {
         let staking_tier = &ctx.accounts.staking_tier;
         let user_stake = &mut ctx.accounts.user_stake;
 
+        verify_stake_owner(
+            user_stake,
+            &ctx.accounts.user.key(),
+            ctx.accounts.position_token_account.as_deref(),
+        )?;
         require!(user_stake.is_active, ErrorCode::StakeNotActive);
 
         let clock = Clock::get()?;
         let current_time = clock.unix_timestamp;
 
-        let rewards = calculate_rewards(user_stake, staking_tier, current_time)?;
+        settle_with_verified_boost(
+            user_stake,
+            staking_pool,
+            &ctx.accounts.user.key(),
+            ctx.remaining_accounts,
+            clock.epoch,
+        )?;
+        update_reward_weight(user_stake, staking_pool);
+        let apy_rewards = calculate_rewards(user_stake, staking_pool, staking_tier, current_time)?;
+        let rewards = apy_rewards + user_stake.accumulated_rewards;
         require!(rewards > 0, ErrorCode::NoRewardsAvailable);
 
         // Update totals
         staking_pool.total_rewards_distributed += rewards;
-        user_stake.rewards_claimed += rewards;
+        accrue_referral_reward(user_stake, staking_pool, rewards);
+        staking_pool.accumulated_rewards_outstanding = staking_pool
+            .accumulated_rewards_outstanding
+            .saturating_sub(user_stake.accumulated_rewards);
+        user_stake.rewards_claimed += apy_rewards;
+        user_stake.accumulated_rewards = 0;
 
         // Transfer rewards to user
         let staking_pool_key = staking_pool.key();
@@ -170,189 +898,2937 @@ pub mod iamai_staking {
 
         Ok(())
     }
-}
 
-fn calculate_rewards(
-    user_stake: &UserStake,
-    staking_tier: &StakingTier,
-    current_time: i64,
-) -> Result<u64> {
-    let staking_duration = std::cmp::min(current_time, user_stake.end_time) - user_stake.start_time;
-    let annual_seconds = 365 * 24 * 60 * 60;
-    
-    let rewards = (user_stake.amount as u128 * staking_tier.apy_basis_points as u128 * staking_duration as u128)
-        / (10000u128 * annual_seconds as u128);
-    
-    Ok(rewards as u64 - user_stake.rewards_claimed)
-}
+    pub fn extend_stake(ctx: Context<ExtendStake>) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let staking_tier = &mut ctx.accounts.staking_tier;
+        let new_staking_tier = &mut ctx.accounts.new_staking_tier;
+        let user_stake = &mut ctx.accounts.user_stake;
 
-#[derive(Accounts)]
-pub struct InitializeStaking<'info> {
-    #[account(
-        init,
-        payer = authority,
-        space = 8 + StakingPool::INIT_SPACE
-    )]
-    pub staking_pool: Account<'info, StakingPool>,
-    
-    pub token_mint: Account<'info, anchor_spl::token::Mint>,
-    
-    #[account(
-        init,
-        payer = authority,
-        seeds = [b"vault", staking_pool.key().as_ref()],
-        bump,
-        token::mint = token_mint,
-        token::authority = vault,
-    )]
-    pub vault: Account<'info, TokenAccount>,
-    
-    #[account(mut)]
-    pub authority: Signer<'info>,
+        verify_stake_owner(
+            user_stake,
+            &ctx.accounts.user.key(),
+            ctx.accounts.position_token_account.as_deref(),
+        )?;
+        require!(user_stake.is_active, ErrorCode::StakeNotActive);
+        require!(!user_stake.is_unbonding, ErrorCode::StakeUnbonding);
+        require!(
+            new_staking_tier.is_active
+                && new_staking_tier.key() != staking_tier.key()
+                && new_staking_tier.duration_days >= staking_tier.duration_days,
+            ErrorCode::InvalidStakingTier
+        );
+
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        // Settle rewards accrued at the old tier APY
+        let rewards = calculate_rewards(user_stake, staking_pool, staking_tier, current_time)?;
+        staking_pool.total_rewards_distributed += rewards;
+        accrue_referral_reward(user_stake, staking_pool, rewards);
+
+        // Move the stake to the new tier without shortening the lock
+        user_stake.tier = new_staking_tier.key();
+        user_stake.start_time = current_time;
+        user_stake.end_time = relocked_end_time(user_stake, new_staking_tier, current_time);
+        user_stake.rewards_claimed = 0;
+
+        staking_tier.total_staked -= user_stake.amount;
+        new_staking_tier.total_staked += user_stake.amount;
+
+        if rewards > 0 {
+            // Transfer rewards to user
+            let staking_pool_key = staking_pool.key();
+            let seeds = &[
+                b"vault",
+                staking_pool_key.as_ref(),
+                &[ctx.bumps.vault],
+            ];
+            let signer = &[&seeds[..]];
+
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.vault.to_account_info(),
+                to: ctx.accounts.user_token_account.to_account_info(),
+                authority: ctx.accounts.vault.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+            token::transfer(cpi_ctx, rewards)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn add_to_stake(
+        ctx: Context<AddToStake>,
+        amount: u64,
+        reset_lock: bool,
+    ) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let staking_tier = &mut ctx.accounts.staking_tier;
+        let user_stake = &mut ctx.accounts.user_stake;
+
+        verify_stake_owner(
+            user_stake,
+            &ctx.accounts.user.key(),
+            ctx.accounts.position_token_account.as_deref(),
+        )?;
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(user_stake.is_active, ErrorCode::StakeNotActive);
+        require!(!user_stake.is_unbonding, ErrorCode::StakeUnbonding);
+        require!(
+            user_stake.delegate == Pubkey::default(),
+            ErrorCode::StakeDelegated
+        );
+        let new_amount = user_stake.amount + amount;
+        record_epoch_amount_change(user_stake, staking_pool, new_amount)?;
+
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        // Settle rewards on the existing amount before it changes
+        settle_with_verified_boost(
+            user_stake,
+            staking_pool,
+            &ctx.accounts.user.key(),
+            ctx.remaining_accounts,
+            clock.epoch,
+        )?;
+        let rewards = settle_rewards(user_stake, staking_pool, staking_tier, current_time)?;
+
+        user_stake.amount += amount;
+        if reset_lock {
+            user_stake.end_time = relocked_end_time(user_stake, staking_tier, current_time);
+        }
+        update_reward_weight(user_stake, staking_pool);
+
+        staking_pool.total_staked += amount;
+        staking_tier.total_staked += amount;
+
+        // Transfer tokens to vault
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.user_token_account.to_account_info(),
+            to: ctx.accounts.vault.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer(cpi_ctx, amount)?;
+
+        transfer_from_vault(
+            &ctx.accounts.vault,
+            &ctx.accounts.user_token_account,
+            &ctx.accounts.token_program,
+            staking_pool.key(),
+            ctx.bumps.vault,
+            rewards,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn partial_unstake(
+        ctx: Context<PartialUnstake>,
+        amount: u64,
+        early_unstake: bool,
+    ) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let staking_tier = &mut ctx.accounts.staking_tier;
+        let user_stake = &mut ctx.accounts.user_stake;
+
+        verify_stake_owner(
+            user_stake,
+            &ctx.accounts.user.key(),
+            ctx.accounts.position_token_account.as_deref(),
+        )?;
+        require!(user_stake.is_active, ErrorCode::StakeNotActive);
+        require!(!user_stake.is_unbonding, ErrorCode::StakeUnbonding);
+        require!(
+            user_stake.delegate == Pubkey::default(),
+            ErrorCode::StakeDelegated
+        );
+        // Withdrawing everything goes through unstake_tokens
+        require!(
+            amount > 0 && amount < user_stake.amount,
+            ErrorCode::InvalidAmount
+        );
+        let remaining_amount = user_stake.amount - amount;
+        record_epoch_amount_change(user_stake, staking_pool, remaining_amount)?;
+
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+        // Stakes backing a live governance vote stay put until voting ends
+        require!(
+            current_time > user_stake.vote_locked_until,
+            ErrorCode::StakeVoteLocked
+        );
+
+        // Penalty only applies to the withdrawn part
+        let mut penalty = 0u64;
+        if current_time < user_stake.end_time {
+            require!(early_unstake, ErrorCode::StakingPeriodNotComplete);
+            require!(
+                user_stake.vest_unlocked_at(current_time),
+                ErrorCode::StakeVestLocked
+            );
+            require!(
+                staking_pool.unstake_mode != UnstakeMode::Cooldown,
+                ErrorCode::EarlyUnstakePenaltyDisabled
+            );
+            penalty = early_unstake_penalty(staking_pool, amount);
+        }
+
+        // Settle rewards on the full amount, then keep accruing on the remainder
+        settle_with_verified_boost(
+            user_stake,
+            staking_pool,
+            &ctx.accounts.user.key(),
+            ctx.remaining_accounts,
+            clock.epoch,
+        )?;
+        let rewards = settle_rewards(user_stake, staking_pool, staking_tier, current_time)?;
+
+        user_stake.amount -= amount;
+        update_reward_weight(user_stake, staking_pool);
+
+        staking_pool.total_staked -= amount;
+        staking_tier.total_staked -= amount;
+
+        if penalty > 0 {
+            collect_penalty(
+                staking_pool,
+                user_stake.user,
+                penalty,
+                &ctx.accounts.vault,
+                &ctx.accounts.token_mint,
+                ctx.accounts.penalty_treasury.as_ref(),
+                &ctx.accounts.token_program,
+                ctx.bumps.vault,
+            )?;
+        }
+
+        transfer_from_vault(
+            &ctx.accounts.vault,
+            &ctx.accounts.user_token_account,
+            &ctx.accounts.token_program,
+            staking_pool.key(),
+            ctx.bumps.vault,
+            amount - penalty + rewards,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn enable_liquid_staking(ctx: Context<EnableLiquidStaking>) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let liquid_tier = &ctx.accounts.liquid_tier;
+
+        require!(
+            staking_pool.receipt_mint == Pubkey::default(),
+            ErrorCode::LiquidStakingAlreadyEnabled
+        );
+        require!(
+            liquid_tier.is_active && liquid_tier.duration_days == 0,
+            ErrorCode::InvalidStakingTier
+        );
+
+        let clock = Clock::get()?;
+
+        staking_pool.liquid_tier = liquid_tier.key();
+        staking_pool.receipt_mint = ctx.accounts.receipt_mint.key();
+        staking_pool.liquid_vault = ctx.accounts.liquid_vault.key();
+        staking_pool.liquid_last_accrual = clock.unix_timestamp;
+        Ok(())
+    }
+
+    pub fn liquid_stake(
+        ctx: Context<LiquidStake>,
+        amount: u64,
+        min_receipt_amount: u64,
+    ) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        accrue_liquid_rewards(
+            &mut ctx.accounts.staking_pool,
+            &ctx.accounts.liquid_tier,
+            &ctx.accounts.vault,
+            &mut ctx.accounts.liquid_vault,
+            &ctx.accounts.token_program,
+            ctx.bumps.vault,
+        )?;
+
+        // Receipt amount is priced at the current vault/supply exchange rate
+        let receipt_amount = convert_liquid_amount(
+            amount,
+            ctx.accounts.receipt_mint.supply,
+            ctx.accounts.liquid_vault.amount,
+        )?;
+        require!(receipt_amount > 0, ErrorCode::InvalidAmount);
+        require!(
+            receipt_amount >= min_receipt_amount,
+            ErrorCode::SlippageExceeded
+        );
+
+        // Transfer tokens to liquid vault
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.user_token_account.to_account_info(),
+            to: ctx.accounts.liquid_vault.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer(cpi_ctx, amount)?;
+
+        // Mint receipt tokens to user
+        let staking_pool_key = ctx.accounts.staking_pool.key();
+        let seeds = &[
+            b"receipt_mint",
+            staking_pool_key.as_ref(),
+            &[ctx.bumps.receipt_mint],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = MintTo {
+            mint: ctx.accounts.receipt_mint.to_account_info(),
+            to: ctx.accounts.user_receipt_account.to_account_info(),
+            authority: ctx.accounts.receipt_mint.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::mint_to(cpi_ctx, receipt_amount)?;
+
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        staking_pool.total_staked += amount;
+        staking_pool.liquid_staked += amount;
+        ctx.accounts.liquid_tier.total_staked += amount;
+
+        Ok(())
+    }
+
+    pub fn liquid_unstake(
+        ctx: Context<LiquidUnstake>,
+        receipt_amount: u64,
+        min_amount_out: u64,
+    ) -> Result<()> {
+        require!(receipt_amount > 0, ErrorCode::InvalidAmount);
+
+        accrue_liquid_rewards(
+            &mut ctx.accounts.staking_pool,
+            &ctx.accounts.liquid_tier,
+            &ctx.accounts.vault,
+            &mut ctx.accounts.liquid_vault,
+            &ctx.accounts.token_program,
+            ctx.bumps.vault,
+        )?;
+
+        // Redeemed amount includes principal plus accrued rewards
+        let amount_out = convert_liquid_amount(
+            receipt_amount,
+            ctx.accounts.liquid_vault.amount,
+            ctx.accounts.receipt_mint.supply,
+        )?;
+        require!(amount_out > 0, ErrorCode::InvalidAmount);
+        require!(amount_out >= min_amount_out, ErrorCode::SlippageExceeded);
+
+        // The redeemed receipts' pro-rata share of principal leaves the totals
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let principal = liquid_principal_share(
+            receipt_amount,
+            staking_pool.liquid_staked,
+            ctx.accounts.receipt_mint.supply,
+        );
+        staking_pool.total_staked -= principal;
+        staking_pool.liquid_staked -= principal;
+        ctx.accounts.liquid_tier.total_staked -= principal;
+
+        // Burn receipt tokens
+        let cpi_accounts = Burn {
+            mint: ctx.accounts.receipt_mint.to_account_info(),
+            from: ctx.accounts.user_receipt_account.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::burn(cpi_ctx, receipt_amount)?;
+
+        // Transfer tokens back to user
+        let staking_pool_key = ctx.accounts.staking_pool.key();
+        let seeds = &[
+            b"liquid_vault",
+            staking_pool_key.as_ref(),
+            &[ctx.bumps.liquid_vault],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.liquid_vault.to_account_info(),
+            to: ctx.accounts.user_token_account.to_account_info(),
+            authority: ctx.accounts.liquid_vault.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, amount_out)?;
+
+        Ok(())
+    }
+
+    pub fn create_lock(
+        ctx: Context<CreateLock>,
+        amount: u64,
+        lock_duration: i64, // seconds
+    ) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let vote_escrow = &mut ctx.accounts.vote_escrow;
+
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(
+            (MIN_LOCK_DURATION..=MAX_LOCK_DURATION).contains(&lock_duration),
+            ErrorCode::InvalidLockDuration
+        );
+
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        vote_escrow.owner = ctx.accounts.user.key();
+        vote_escrow.pool = staking_pool.key();
+        vote_escrow.amount = amount;
+        vote_escrow.lock_start = current_time;
+        vote_escrow.unlock_time = current_time + lock_duration;
+        vote_escrow.history_start = 0;
+        vote_escrow.checkpoints = Vec::new();
+        vote_escrow.write_checkpoint(current_time);
+
+        staking_pool.total_escrowed += amount;
+
+        // Transfer tokens to vault
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.user_token_account.to_account_info(),
+            to: ctx.accounts.vault.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer(cpi_ctx, amount)?;
+
+        Ok(())
+    }
+
+    pub fn increase_amount(
+        ctx: Context<ModifyLock>,
+        amount: u64,
+    ) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let vote_escrow = &mut ctx.accounts.vote_escrow;
+
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let clock = Clock::get()?;
+        require!(
+            clock.unix_timestamp < vote_escrow.unlock_time,
+            ErrorCode::LockExpired
+        );
+
+        vote_escrow.amount += amount;
+        vote_escrow.write_checkpoint(clock.unix_timestamp);
+        staking_pool.total_escrowed += amount;
+
+        // Transfer tokens to vault
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.user_token_account.to_account_info(),
+            to: ctx.accounts.vault.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer(cpi_ctx, amount)?;
+
+        Ok(())
+    }
+
+    pub fn extend_lock(
+        ctx: Context<ExtendLock>,
+        new_unlock_time: i64,
+    ) -> Result<()> {
+        let vote_escrow = &mut ctx.accounts.vote_escrow;
+
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        require!(
+            new_unlock_time > vote_escrow.unlock_time,
+            ErrorCode::InvalidLockDuration
+        );
+        require!(
+            (MIN_LOCK_DURATION..=MAX_LOCK_DURATION).contains(&(new_unlock_time - current_time)),
+            ErrorCode::InvalidLockDuration
+        );
+
+        vote_escrow.unlock_time = new_unlock_time;
+        vote_escrow.write_checkpoint(current_time);
+        Ok(())
+    }
+
+    pub fn withdraw_escrow(ctx: Context<WithdrawEscrow>) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let vote_escrow = &ctx.accounts.vote_escrow;
+
+        let clock = Clock::get()?;
+        require!(
+            clock.unix_timestamp >= vote_escrow.unlock_time,
+            ErrorCode::LockNotExpired
+        );
+
+        staking_pool.total_escrowed -= vote_escrow.amount;
+
+        // Transfer tokens back to user
+        let staking_pool_key = staking_pool.key();
+        let seeds = &[
+            b"vault",
+            staking_pool_key.as_ref(),
+            &[ctx.bumps.vault],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.vault.to_account_info(),
+            to: ctx.accounts.user_token_account.to_account_info(),
+            authority: ctx.accounts.vault.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, vote_escrow.amount)?;
+
+        Ok(())
+    }
+
+    pub fn get_voting_power(
+        ctx: Context<GetVotingPower>,
+        timestamp: i64,
+    ) -> Result<u64> {
+        ctx.accounts.vote_escrow.voting_power_at(timestamp)
+    }
+
+    pub fn tokenize_stake(ctx: Context<TokenizeStake>) -> Result<()> {
+        let user_stake = &mut ctx.accounts.user_stake;
+
+        require!(user_stake.is_active, ErrorCode::StakeNotActive);
+        require!(
+            user_stake.position_mint == Pubkey::default(),
+            ErrorCode::StakeAlreadyTokenized
+        );
+        // A vesting grant cannot be sold off before it vests
+        require!(
+            user_stake.vest_unlocked_at(Clock::get()?.unix_timestamp),
+            ErrorCode::StakeVestLocked
+        );
+
+        user_stake.position_mint = ctx.accounts.position_mint.key();
+
+        let user_stake_key = user_stake.key();
+        let seeds = &[
+            b"position_mint",
+            user_stake_key.as_ref(),
+            &[ctx.bumps.position_mint],
+        ];
+        let signer = &[&seeds[..]];
+
+        // Mint the single position NFT to the staker
+        let cpi_accounts = MintTo {
+            mint: ctx.accounts.position_mint.to_account_info(),
+            to: ctx.accounts.user_position_account.to_account_info(),
+            authority: ctx.accounts.position_mint.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::mint_to(cpi_ctx, 1)?;
+
+        // Revoke mint authority so the position stays 1-of-1
+        let cpi_accounts = SetAuthority {
+            current_authority: ctx.accounts.position_mint.to_account_info(),
+            account_or_mint: ctx.accounts.position_mint.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::set_authority(cpi_ctx, AuthorityType::MintTokens, None)?;
+
+        Ok(())
+    }
+
+    pub fn add_reward_token(ctx: Context<AddRewardToken>) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let reward_mint = ctx.accounts.reward_mint.key();
+
+        require!(
+            staking_pool.reward_tokens.len() < MAX_REWARD_TOKENS,
+            ErrorCode::TooManyRewardTokens
+        );
+        require!(
+            staking_pool
+                .reward_tokens
+                .iter()
+                .all(|reward_token| reward_token.mint != reward_mint),
+            ErrorCode::RewardTokenAlreadyAdded
+        );
+
+        staking_pool.reward_tokens.push(RewardToken {
+            mint: reward_mint,
+            vault: ctx.accounts.reward_vault.key(),
+            acc_reward_per_share: 0,
+            total_deposited: 0,
+            undistributed: 0,
+        });
+        Ok(())
+    }
+
+    pub fn deposit_rewards(
+        ctx: Context<DepositRewards>,
+        amount: u64,
+    ) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let reward_vault_key = ctx.accounts.reward_vault.key();
+
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let total_effective_stake = staking_pool.total_effective_stake;
+        let reward_token = staking_pool
+            .reward_tokens
+            .iter_mut()
+            .find(|reward_token| reward_token.vault == reward_vault_key)
+            .ok_or(ErrorCode::InvalidRewardToken)?;
+
+        reward_token.total_deposited += amount;
+        reward_token.distribute(amount, total_effective_stake);
+
+        // Transfer rewards to reward vault
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.depositor_token_account.to_account_info(),
+            to: ctx.accounts.reward_vault.to_account_info(),
+            authority: ctx.accounts.depositor.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer(cpi_ctx, amount)?;
+
+        Ok(())
+    }
+
+    pub fn claim_reward_token(ctx: Context<ClaimRewardToken>) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let user_stake = &mut ctx.accounts.user_stake;
+        let reward_vault = &ctx.accounts.reward_vault;
+
+        verify_stake_owner(
+            user_stake,
+            &ctx.accounts.user.key(),
+            ctx.accounts.position_token_account.as_deref(),
+        )?;
+
+        let index = staking_pool
+            .reward_tokens
+            .iter()
+            .position(|reward_token| reward_token.vault == reward_vault.key())
+            .ok_or(ErrorCode::InvalidRewardToken)?;
+
+        let clock = Clock::get()?;
+        settle_with_verified_boost(
+            user_stake,
+            staking_pool,
+            &ctx.accounts.user.key(),
+            ctx.remaining_accounts,
+            clock.epoch,
+        )?;
+        update_reward_weight(user_stake, staking_pool);
+        let rewards = user_stake.reward_token_pending[index];
+        require!(rewards > 0, ErrorCode::NoRewardsAvailable);
+        user_stake.reward_token_pending[index] = 0;
+
+        // Transfer rewards to user
+        let staking_pool_key = staking_pool.key();
+        let reward_mint = reward_vault.mint;
+        let seeds = &[
+            b"reward_vault",
+            staking_pool_key.as_ref(),
+            reward_mint.as_ref(),
+            &[ctx.bumps.reward_vault],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = Transfer {
+            from: reward_vault.to_account_info(),
+            to: ctx.accounts.user_reward_account.to_account_info(),
+            authority: reward_vault.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, rewards)?;
+
+        Ok(())
+    }
+
+    pub fn create_delegate_record(
+        ctx: Context<CreateDelegateRecord>,
+        delegate: Pubkey,
+    ) -> Result<()> {
+        let delegate_record = &mut ctx.accounts.delegate_record;
+        delegate_record.pool = ctx.accounts.staking_pool.key();
+        delegate_record.delegate = delegate;
+        delegate_record.delegated_power = 0;
+        delegate_record.delegator_count = 0;
+        delegate_record.vote_locked_until = 0;
+        Ok(())
+    }
+
+    pub fn delegate_stake_voting_power(ctx: Context<DelegateStakeVotingPower>) -> Result<()> {
+        let user_stake = &mut ctx.accounts.user_stake;
+        let delegate_record = &mut ctx.accounts.delegate_record;
+
+        verify_stake_owner(
+            user_stake,
+            &ctx.accounts.user.key(),
+            ctx.accounts.position_token_account.as_deref(),
+        )?;
+        require!(user_stake.is_active, ErrorCode::StakeNotActive);
+        require!(!user_stake.is_unbonding, ErrorCode::StakeUnbonding);
+        require!(
+            delegate_record.delegate != user_stake.user,
+            ErrorCode::InvalidDelegate
+        );
+        require!(
+            delegate_record.delegate != user_stake.delegate,
+            ErrorCode::AlreadyDelegated
+        );
+
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+        // Stakes backing a live governance vote stay put until voting ends
+        require!(
+            current_time > user_stake.vote_locked_until,
+            ErrorCode::StakeVoteLocked
+        );
+
+        // Redelegation moves power off the previous delegate first
+        if user_stake.delegate != Pubkey::default() {
+            let previous_delegate_record = ctx
+                .accounts
+                .previous_delegate_record
+                .as_mut()
+                .ok_or(ErrorCode::InvalidDelegate)?;
+            require!(
+                previous_delegate_record.delegate == user_stake.delegate,
+                ErrorCode::InvalidDelegate
+            );
+            require!(
+                current_time > previous_delegate_record.vote_locked_until,
+                ErrorCode::StakeVoteLocked
+            );
+            previous_delegate_record.remove_power(user_stake.amount);
+        }
+
+        delegate_record.add_power(user_stake.amount);
+
+        user_stake.delegate = delegate_record.delegate;
+        Ok(())
+    }
+
+    // Locks the stake until `unlock_time` while it backs a governance vote.
+    // Called by the governance program with the voter's signature; only the
+    // stake's owner can lock it, and a lock is only ever extended.
+    pub fn lock_stake_for_vote(ctx: Context<LockStakeForVote>, unlock_time: i64) -> Result<()> {
+        let user_stake = &mut ctx.accounts.user_stake;
+
+        verify_stake_owner(
+            user_stake,
+            &ctx.accounts.user.key(),
+            ctx.accounts.position_token_account.as_deref(),
+        )?;
+
+        user_stake.vote_locked_until = std::cmp::max(user_stake.vote_locked_until, unlock_time);
+        Ok(())
+    }
+
+    // Locks the delegate's record until `unlock_time` after the delegate votes
+    // with the delegated power, so delegators cannot pull it out mid-vote.
+    pub fn lock_delegate_record_for_vote(
+        ctx: Context<LockDelegateRecordForVote>,
+        unlock_time: i64,
+    ) -> Result<()> {
+        let delegate_record = &mut ctx.accounts.delegate_record;
+        delegate_record.vote_locked_until =
+            std::cmp::max(delegate_record.vote_locked_until, unlock_time);
+        Ok(())
+    }
+
+    pub fn undelegate_stake_voting_power(ctx: Context<UndelegateStakeVotingPower>) -> Result<()> {
+        let user_stake = &mut ctx.accounts.user_stake;
+        let delegate_record = &mut ctx.accounts.delegate_record;
+
+        verify_stake_owner(
+            user_stake,
+            &ctx.accounts.user.key(),
+            ctx.accounts.position_token_account.as_deref(),
+        )?;
+        require!(
+            user_stake.delegate != Pubkey::default(),
+            ErrorCode::StakeNotDelegated
+        );
+
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+        // The delegate has voted with this power on a live proposal
+        require!(
+            current_time > delegate_record.vote_locked_until,
+            ErrorCode::StakeVoteLocked
+        );
+
+        delegate_record.remove_power(user_stake.amount);
+
+        user_stake.delegate = Pubkey::default();
+        Ok(())
+    }
+}
+
+fn calculate_rewards(
+    user_stake: &UserStake,
+    staking_pool: &StakingPool,
+    staking_tier: &StakingTier,
+    current_time: i64,
+) -> Result<u64> {
+    // Epoch pools pay through claim_epoch_rewards instead of tier APY
+    if staking_pool.reward_mode == RewardMode::Epoch {
+        return Ok(0);
+    }
+
+    // Rewards stop accruing once the stake starts unbonding
+    let accrual_end = if user_stake.is_unbonding {
+        std::cmp::min(user_stake.end_time, user_stake.unbonding_start)
+    } else {
+        user_stake.end_time
+    };
+    // Accrual restarted after the lock ended earns nothing further
+    let accrual_until = std::cmp::min(current_time, accrual_end);
+    let annual_seconds = 365 * 24 * 60 * 60;
+
+    // Each stretch of the accrual window earns the APY in effect at the time
+    let rewards = (user_stake.amount as u128
+        * staking_tier.apy_seconds_between(user_stake.start_time, accrual_until))
+        / (10000u128 * annual_seconds as u128);
+
+    // Merged APY history can only lower the total, never below what was paid
+    Ok((rewards as u64).saturating_sub(user_stake.rewards_claimed))
+}
+
+// Settles APY and accumulator rewards owed up to `current_time` and restarts
+// APY accrual from there. Returns the amount the caller must pay out.
+fn settle_rewards(
+    user_stake: &mut UserStake,
+    staking_pool: &mut StakingPool,
+    staking_tier: &StakingTier,
+    current_time: i64,
+) -> Result<u64> {
+    settle_accumulated_rewards(user_stake, staking_pool);
+    let apy_rewards = calculate_rewards(user_stake, staking_pool, staking_tier, current_time)?;
+    let rewards = apy_rewards + user_stake.accumulated_rewards;
+
+    staking_pool.total_rewards_distributed += rewards;
+    accrue_referral_reward(user_stake, staking_pool, rewards);
+    staking_pool.accumulated_rewards_outstanding = staking_pool
+        .accumulated_rewards_outstanding
+        .saturating_sub(user_stake.accumulated_rewards);
+
+    user_stake.accumulated_rewards = 0;
+    user_stake.start_time = current_time;
+    user_stake.rewards_claimed = 0;
+    Ok(rewards)
+}
+
+// End of a lock restarted on `staking_tier` at `current_time`. A lock is never
+// shortened.
+fn relocked_end_time(user_stake: &UserStake, staking_tier: &StakingTier, current_time: i64) -> i64 {
+    let new_end_time = current_time + (staking_tier.duration_days as i64 * 24 * 60 * 60);
+    std::cmp::max(user_stake.end_time, new_end_time)
+}
+
+// Initializes a freshly created stake account owned by `staker`. The caller
+// transfers the tokens into the vault.
+fn open_stake<'info>(
+    user_stake: &mut Account<'info, UserStake>,
+    staking_pool: &mut Account<'info, StakingPool>,
+    staking_tier: &mut Account<'info, StakingTier>,
+    staker: Pubkey,
+    amount: u64,
+    vest_locked: bool,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let start_time = clock.unix_timestamp;
+    let end_time = start_time + (staking_tier.duration_days as i64 * 24 * 60 * 60);
+
+    // Initialize user stake
+    user_stake.user = staker;
+    user_stake.pool = staking_pool.key();
+    user_stake.tier = staking_tier.key();
+    user_stake.amount = amount;
+    user_stake.start_time = start_time;
+    user_stake.end_time = end_time;
+    user_stake.rewards_claimed = 0;
+    user_stake.is_active = true;
+    user_stake.is_unbonding = false;
+    user_stake.unbonding_start = 0;
+    user_stake.unbonding_end = 0;
+    user_stake.accumulated_rewards = 0;
+    user_stake.delegate = Pubkey::default();
+    user_stake.position_mint = Pubkey::default();
+    user_stake.reward_token_pending = [0; MAX_REWARD_TOKENS];
+    user_stake.boost_bps = 0;
+    user_stake.effective_amount = 0;
+    user_stake.staked_since_epoch = clock.epoch;
+    // The epoch in progress was not staked through, so it earns nothing
+    user_stake.next_claim_epoch = staking_pool.current_reward_epoch;
+    user_stake.epoch_amount_from = staking_pool.current_reward_epoch + 1;
+    user_stake.prior_epoch_amount = 0;
+    user_stake.vest_locked = vest_locked;
+    user_stake.referrer = Pubkey::default();
+    user_stake.referral_rewards_owed = 0;
+    user_stake.vote_locked_until = 0;
+    update_reward_weight(user_stake, staking_pool);
+
+    // Update pool and tier totals
+    staking_pool.total_staked += amount;
+    staking_tier.total_staked += amount;
+    Ok(())
+}
+
+// A referrer must be an established staker in the pool: a separate wallet
+// cannot refer itself into a share without first holding a stake there.
+fn validate_referrer(referrer_stake: &UserStake, referee: &Pubkey, current_epoch: u64) -> Result<()> {
+    require!(
+        referrer_stake.user != *referee && referrer_stake.referrer != *referee,
+        ErrorCode::InvalidReferrer
+    );
+    require!(
+        referrer_stake.is_active
+            && !referrer_stake.is_unbonding
+            && current_epoch.saturating_sub(referrer_stake.staked_since_epoch)
+                >= MIN_REFERRER_STAKE_EPOCHS,
+        ErrorCode::InvalidReferrer
+    );
+    Ok(())
+}
+
+// Credits the referrer with their share of rewards paid to a referred stake.
+// The share is paid on top, so the referee's own rewards are unchanged.
+fn accrue_referral_reward(user_stake: &mut UserStake, staking_pool: &mut StakingPool, rewards: u64) {
+    if user_stake.referrer == Pubkey::default() || staking_pool.referral_bps == 0 {
+        return;
+    }
+
+    let referral_reward = (rewards as u128 * staking_pool.referral_bps as u128 / 10000) as u64;
+    user_stake.referral_rewards_owed += referral_reward;
+    staking_pool.referral_rewards_outstanding += referral_reward;
+}
+
+// Records a change to a stake's amount in the epoch in progress: that epoch
+// only counts the smallest balance held through it. Finalized epochs must be
+// claimed first, since they are weighted by the balance before the change.
+fn record_epoch_amount_change(
+    user_stake: &mut UserStake,
+    staking_pool: &mut StakingPool,
+    new_amount: u64,
+) -> Result<()> {
+    if staking_pool.reward_mode != RewardMode::Epoch {
+        return Ok(());
+    }
+
+    require!(
+        user_stake.next_claim_epoch >= staking_pool.current_reward_epoch,
+        ErrorCode::EpochRewardsUnclaimed
+    );
+    lower_epoch_weight(user_stake, staking_pool, new_amount);
+    Ok(())
+}
+
+// Caps the stake's weight for the epoch in progress at `new_amount` and
+// removes the difference from the pool's eligible stake.
+// emergency_withdraw is the only way out of a paused pool, so the vote, vest
+// and delegation locks that otherwise hold a stake in place do not apply.
+fn check_emergency_exit(staking_pool: &StakingPool, user_stake: &UserStake) -> Result<()> {
+    require!(staking_pool.is_paused, ErrorCode::PoolNotPaused);
+    require!(user_stake.is_active, ErrorCode::StakeNotActive);
+    Ok(())
+}
+
+// Forfeits the stake's share of its next unclaimed epoch: the share is marked
+// claimed so no one else can take it, and leaves epoch_rewards_outstanding so
+// it returns to the reward reserve.
+fn release_epoch_share(
+    user_stake: &mut UserStake,
+    staking_pool: &mut StakingPool,
+    epoch_record: &mut EpochRecord,
+) -> Result<u64> {
+    require!(
+        epoch_record.epoch == user_stake.next_claim_epoch,
+        ErrorCode::InvalidEpochRecord
+    );
+    let share = epoch_record.share_of(user_stake.epoch_weight(epoch_record.epoch));
+
+    user_stake.next_claim_epoch += 1;
+    epoch_record.rewards_claimed += share;
+    staking_pool.epoch_rewards_outstanding -= share;
+    Ok(share)
+}
+
+fn lower_epoch_weight(user_stake: &mut UserStake, staking_pool: &mut StakingPool, new_amount: u64) {
+    let current_epoch = staking_pool.current_reward_epoch;
+    let held_through_epoch = user_stake.epoch_weight(current_epoch);
+    let weight = std::cmp::min(held_through_epoch, new_amount);
+
+    staking_pool.epoch_eligible_stake -= held_through_epoch - weight;
+    user_stake.prior_epoch_amount = weight;
+    user_stake.epoch_amount_from = current_epoch + 1;
+}
+
+fn transfer_from_vault<'info>(
+    vault: &Account<'info, TokenAccount>,
+    to: &Account<'info, TokenAccount>,
+    token_program: &Program<'info, Token>,
+    staking_pool_key: Pubkey,
+    vault_bump: u8,
+    amount: u64,
+) -> Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+
+    let seeds = &[
+        b"vault",
+        staking_pool_key.as_ref(),
+        &[vault_bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    let cpi_accounts = Transfer {
+        from: vault.to_account_info(),
+        to: to.to_account_info(),
+        authority: vault.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+    token::transfer(cpi_ctx, amount)
+}
+
+// Stakes represented by a position NFT belong to whoever holds the NFT;
+// otherwise only the original staker may act on them.
+fn verify_stake_owner(
+    user_stake: &UserStake,
+    owner: &Pubkey,
+    position_token_account: Option<&TokenAccount>,
+) -> Result<()> {
+    if user_stake.position_mint == Pubkey::default() {
+        require_keys_eq!(user_stake.user, *owner, ErrorCode::Unauthorized);
+        return Ok(());
+    }
+
+    let position_token_account =
+        position_token_account.ok_or(ErrorCode::PositionTokenRequired)?;
+    require!(
+        position_token_account.mint == user_stake.position_mint
+            && position_token_account.owner == *owner
+            && position_token_account.amount == 1,
+        ErrorCode::Unauthorized
+    );
+    Ok(())
+}
+
+// Moves rewards accrued through the per-share accumulator into
+// `accumulated_rewards` and brings the reward debt up to date.
+fn settle_accumulated_rewards(user_stake: &mut UserStake, staking_pool: &StakingPool) {
+    let weight = user_stake.effective_amount as u128;
+    let accrued = weight * staking_pool.acc_reward_per_share / ACC_PRECISION;
+    user_stake.accumulated_rewards += (accrued - user_stake.reward_debt) as u64;
+    user_stake.reward_debt = accrued;
+
+    for (index, reward_token) in staking_pool.reward_tokens.iter().enumerate() {
+        let accrued = weight * reward_token.acc_reward_per_share / ACC_PRECISION;
+        user_stake.reward_token_pending[index] += (accrued - user_stake.reward_token_debts[index]) as u64;
+        user_stake.reward_token_debts[index] = accrued;
+    }
+}
+
+// Recomputes the stake's boosted weight, keeps the pool's effective total in
+// step and resets reward debts to the new weight. Call after settling.
+// Re-checks the stake's boosts against the accounts the owner presented
+// before settling. Rewards accrued on boosts that can no longer be verified
+// are forfeited back to the pool and the boost is dropped; callers refresh
+// the reward weight afterwards.
+fn settle_with_verified_boost(
+    user_stake: &mut UserStake,
+    staking_pool: &mut StakingPool,
+    owner: &Pubkey,
+    boost_accounts: &[AccountInfo],
+    current_epoch: u64,
+) -> Result<()> {
+    let boost_bps =
+        calculate_boost_bps(staking_pool, user_stake, owner, boost_accounts, current_epoch)?;
+    if boost_bps >= user_stake.boost_bps {
+        settle_accumulated_rewards(user_stake, staking_pool);
+        user_stake.boost_bps = boost_bps;
+        return Ok(());
+    }
+
+    let weight = user_stake.effective_amount as u128;
+    let verified_bps = 10000 + boost_bps as u128;
+    let recorded_bps = 10000 + user_stake.boost_bps as u128;
+
+    let accrued = weight * staking_pool.acc_reward_per_share / ACC_PRECISION;
+    let pending = accrued - user_stake.reward_debt;
+    let earned = pending * verified_bps / recorded_bps;
+    user_stake.accumulated_rewards += earned as u64;
+    user_stake.reward_debt = accrued;
+    staking_pool.accumulated_rewards_outstanding = staking_pool
+        .accumulated_rewards_outstanding
+        .saturating_sub((pending - earned) as u64);
+
+    for (index, reward_token) in staking_pool.reward_tokens.iter_mut().enumerate() {
+        let accrued = weight * reward_token.acc_reward_per_share / ACC_PRECISION;
+        let pending = accrued - user_stake.reward_token_debts[index];
+        let earned = pending * verified_bps / recorded_bps;
+        user_stake.reward_token_pending[index] += earned as u64;
+        user_stake.reward_token_debts[index] = accrued;
+        reward_token.undistributed += (pending - earned) as u64;
+    }
+
+    user_stake.boost_bps = boost_bps;
+    Ok(())
+}
+
+fn update_reward_weight(user_stake: &mut UserStake, staking_pool: &mut StakingPool) {
+    let effective_amount = accumulator_weight(user_stake);
+    staking_pool.total_effective_stake =
+        staking_pool.total_effective_stake - user_stake.effective_amount + effective_amount;
+    user_stake.effective_amount = effective_amount;
+
+    let weight = effective_amount as u128;
+    user_stake.reward_debt = weight * staking_pool.acc_reward_per_share / ACC_PRECISION;
+
+    for (index, reward_token) in staking_pool.reward_tokens.iter().enumerate() {
+        user_stake.reward_token_debts[index] = weight * reward_token.acc_reward_per_share / ACC_PRECISION;
+    }
+}
+
+// Only active, bonded stake shares in accumulator distributions, scaled up
+// by any boost the stake qualified for.
+fn accumulator_weight(user_stake: &UserStake) -> u64 {
+    if user_stake.is_active && !user_stake.is_unbonding {
+        (user_stake.amount as u128 * (10000 + user_stake.boost_bps as u128) / 10000) as u64
+    } else {
+        0
+    }
+}
+
+// Sums the boosts a stake qualifies for. NFT boosts are proven by passing a
+// (token account, Metaplex metadata) pair per collection in remaining_accounts.
+fn calculate_boost_bps(
+    staking_pool: &StakingPool,
+    user_stake: &UserStake,
+    owner: &Pubkey,
+    remaining_accounts: &[AccountInfo],
+    current_epoch: u64,
+) -> Result<u16> {
+    let mut boost_bps = 0u16;
+
+    for boost_source in staking_pool.boost_sources.iter() {
+        let qualifies = match boost_source.kind {
+            BoostKind::Loyalty { min_epochs } => {
+                current_epoch.saturating_sub(user_stake.staked_since_epoch) >= min_epochs
+            }
+            BoostKind::NftCollection { collection } => remaining_accounts
+                .chunks_exact(2)
+                .any(|pair| holds_collection_nft(&pair[0], &pair[1], owner, &collection)),
+        };
+        if qualifies {
+            boost_bps += boost_source.multiplier_bps;
+        }
+    }
+
+    Ok(std::cmp::min(boost_bps, MAX_BOOST_BPS))
+}
+
+fn holds_collection_nft(
+    token_account_info: &AccountInfo,
+    metadata_info: &AccountInfo,
+    owner: &Pubkey,
+    collection: &Pubkey,
+) -> bool {
+    if token_account_info.owner != &token::ID || metadata_info.owner != &TOKEN_METADATA_PROGRAM_ID {
+        return false;
+    }
+    let Ok(data) = token_account_info.try_borrow_data() else {
+        return false;
+    };
+    let Ok(token_account) = TokenAccount::try_deserialize(&mut &data[..]) else {
+        return false;
+    };
+    if token_account.owner != *owner || token_account.amount == 0 {
+        return false;
+    }
+
+    let (metadata_key, _) = Pubkey::find_program_address(
+        &[
+            b"metadata",
+            TOKEN_METADATA_PROGRAM_ID.as_ref(),
+            token_account.mint.as_ref(),
+        ],
+        &TOKEN_METADATA_PROGRAM_ID,
+    );
+    if metadata_info.key() != metadata_key {
+        return false;
+    }
+
+    let Ok(metadata) = metadata_info.try_borrow_data() else {
+        return false;
+    };
+    read_verified_collection(&metadata) == Some(*collection)
+}
+
+// Reads the verified collection key from a Metaplex metadata account by
+// walking its borsh layout up to the `collection` field.
+fn read_verified_collection(data: &[u8]) -> Option<Pubkey> {
+    fn read_u32(data: &[u8], offset: usize) -> Option<usize> {
+        let bytes = data.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().ok()?) as usize)
+    }
+
+    // key, update_authority, mint
+    let mut offset = 1 + 32 + 32;
+    // name, symbol, uri
+    for _ in 0..3 {
+        offset += 4 + read_u32(data, offset)?;
+    }
+    // seller_fee_basis_points
+    offset += 2;
+    // creators: Option<Vec<Creator>>, 34 bytes per creator
+    if *data.get(offset)? == 1 {
+        offset += 1 + 4 + read_u32(data, offset + 1)? * 34;
+    } else {
+        offset += 1;
+    }
+    // primary_sale_happened, is_mutable
+    offset += 2;
+    // edition_nonce, token_standard: Option<u8>
+    for _ in 0..2 {
+        offset += if *data.get(offset)? == 1 { 2 } else { 1 };
+    }
+    // collection: Option<Collection { verified, key }>
+    if *data.get(offset)? != 1 || *data.get(offset + 1)? != 1 {
+        return None;
+    }
+    Pubkey::try_from(data.get(offset + 2..offset + 34)?).ok()
+}
+
+// Part of `amount` withheld when it is unstaked before the lock ends.
+fn early_unstake_penalty(staking_pool: &StakingPool, amount: u64) -> u64 {
+    (amount as u128 * staking_pool.early_unstake_penalty as u128 / 10000) as u64
+}
+
+// Where a penalty goes. Redistribution falls back to burning when nobody is
+// left to redistribute to.
+fn penalty_destination(staking_pool: &StakingPool) -> PenaltyDestination {
+    match staking_pool.penalty_destination {
+        PenaltyDestination::Redistribute if staking_pool.total_effective_stake == 0 => {
+            PenaltyDestination::Burn
+        }
+        destination => destination,
+    }
+}
+
+// Penalty stays in the vault and is paid out to remaining stakers through
+// the per-share accumulator. Only what the accumulator can pay out is
+// reserved; rounding dust stays in the reward reserve.
+fn redistribute_penalty(staking_pool: &mut StakingPool, penalty: u64) {
+    let increment = penalty as u128 * ACC_PRECISION / staking_pool.total_effective_stake as u128;
+    staking_pool.acc_reward_per_share += increment;
+    staking_pool.accumulated_rewards_outstanding +=
+        (increment * staking_pool.total_effective_stake as u128 / ACC_PRECISION) as u64;
+}
+
+// Routes an early-unstake penalty held in the vault to the pool's configured
+// destination. Must run after the penalized stake's weight has been updated.
+#[allow(clippy::too_many_arguments)]
+fn collect_penalty<'info>(
+    staking_pool: &mut Account<'info, StakingPool>,
+    user: Pubkey,
+    penalty: u64,
+    vault: &Account<'info, TokenAccount>,
+    token_mint: &Account<'info, Mint>,
+    penalty_treasury: Option<&Account<'info, TokenAccount>>,
+    token_program: &Program<'info, Token>,
+    vault_bump: u8,
+) -> Result<()> {
+    let destination = penalty_destination(staking_pool);
+
+    let staking_pool_key = staking_pool.key();
+    let seeds = &[
+        b"vault",
+        staking_pool_key.as_ref(),
+        &[vault_bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    match destination {
+        PenaltyDestination::Burn => {
+            let cpi_accounts = Burn {
+                mint: token_mint.to_account_info(),
+                from: vault.to_account_info(),
+                authority: vault.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+            token::burn(cpi_ctx, penalty)?;
+        }
+        PenaltyDestination::Treasury => {
+            let penalty_treasury = penalty_treasury.ok_or(ErrorCode::PenaltyTreasuryRequired)?;
+            let cpi_accounts = Transfer {
+                from: vault.to_account_info(),
+                to: penalty_treasury.to_account_info(),
+                authority: vault.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+            token::transfer(cpi_ctx, penalty)?;
+        }
+        PenaltyDestination::Redistribute => redistribute_penalty(staking_pool, penalty),
+    }
+
+    staking_pool.total_penalties_collected += penalty;
+
+    emit!(PenaltyCollected {
+        pool: staking_pool_key,
+        user,
+        amount: penalty,
+        destination,
+    });
+
+    Ok(())
+}
+
+// Moves rewards earned by the liquid vault since the last accrual out of the
+// pool vault, at the liquid tier APY. Rewards are capped at the pool's reward
+// reserve so principal is never paid out as rewards.
+fn accrue_liquid_rewards<'info>(
+    staking_pool: &mut Account<'info, StakingPool>,
+    liquid_tier: &Account<'info, StakingTier>,
+    vault: &Account<'info, TokenAccount>,
+    liquid_vault: &mut Account<'info, TokenAccount>,
+    token_program: &Program<'info, Token>,
+    vault_bump: u8,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let current_time = clock.unix_timestamp;
+    let last_accrual = staking_pool.liquid_last_accrual;
+    staking_pool.liquid_last_accrual = current_time;
+
+    // Nothing accrues while the pool is paused
+    if current_time <= last_accrual || liquid_vault.amount == 0 || staking_pool.is_paused {
+        return Ok(());
+    }
+
+    // Each stretch accrues at the APY in effect then, so a rate change does
+    // not reach back over time not yet accrued
+    let annual_seconds = 365 * 24 * 60 * 60;
+    let accrued = (liquid_vault.amount as u128
+        * liquid_tier.apy_seconds_between(last_accrual, current_time))
+        / (10000u128 * annual_seconds as u128);
+    let reward_reserve = reward_reserve(staking_pool, vault);
+    let rewards = std::cmp::min(accrued as u64, reward_reserve);
+
+    if rewards == 0 {
+        return Ok(());
+    }
+
+    staking_pool.total_rewards_distributed += rewards;
+
+    let staking_pool_key = staking_pool.key();
+    let seeds = &[
+        b"vault",
+        staking_pool_key.as_ref(),
+        &[vault_bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    let cpi_accounts = Transfer {
+        from: vault.to_account_info(),
+        to: liquid_vault.to_account_info(),
+        authority: vault.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+    token::transfer(cpi_ctx, rewards)?;
+
+    liquid_vault.reload()?;
+    Ok(())
+}
+
+// Vault balance not owed to stakers or lockers as principal or accumulated rewards.
+fn reward_reserve(staking_pool: &StakingPool, vault: &TokenAccount) -> u64 {
+    vault.amount.saturating_sub(
+        staking_pool.vault_staked()
+            + staking_pool.total_unbonding
+            + staking_pool.total_escrowed
+            + staking_pool.accumulated_rewards_outstanding
+            + staking_pool.epoch_rewards_outstanding
+            + staking_pool.referral_rewards_outstanding,
+    )
+}
+
+// Converts between underlying tokens and receipt tokens. `numerator_total` is
+// the side being converted into and `denominator_total` the side being
+// converted from, each padded with the virtual offset.
+fn convert_liquid_amount(amount: u64, numerator_total: u64, denominator_total: u64) -> Result<u64> {
+    let converted = (amount as u128)
+        .checked_mul(numerator_total as u128 + LIQUID_VIRTUAL_OFFSET)
+        .ok_or(ErrorCode::MathOverflow)?
+        / (denominator_total as u128 + LIQUID_VIRTUAL_OFFSET);
+    u64::try_from(converted).map_err(|_| error!(ErrorCode::MathOverflow))
+}
+
+// Principal backing `receipt_amount` of `receipt_supply` receipts. The last
+// receipts out take whatever principal is left, so nothing is stranded by
+// rounding.
+fn liquid_principal_share(receipt_amount: u64, liquid_staked: u64, receipt_supply: u64) -> u64 {
+    if receipt_amount >= receipt_supply {
+        return liquid_staked;
+    }
+    (liquid_staked as u128 * receipt_amount as u128 / receipt_supply as u128) as u64
+}
+
+#[derive(Accounts)]
+pub struct InitializeStaking<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + StakingPool::INIT_SPACE
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    
+    pub token_mint: Account<'info, anchor_spl::token::Mint>,
+    
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"vault", staking_pool.key().as_ref()],
+        bump,
+        token::mint = token_mint,
+        token::authority = vault,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct SetGuardian<'info> {
+    #[account(mut, has_one = authority)]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetPoolPaused<'info> {
+    #[account(
+        mut,
+        constraint = signer.key() == staking_pool.authority
+            || signer.key() == staking_pool.guardian
+            @ ErrorCode::Unauthorized,
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct EmergencyWithdraw<'info> {
+    #[account(mut)]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        address = user_stake.tier @ ErrorCode::InvalidStakingTier,
+    )]
+    pub staking_tier: Account<'info, StakingTier>,
+
+    #[account(
+        mut,
+        constraint = user_stake.pool == staking_pool.key() @ ErrorCode::StakePoolMismatch,
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    #[account(
+        mut,
+        seeds = [b"delegate_record", staking_pool.key().as_ref(), user_stake.delegate.as_ref()],
+        bump,
+    )]
+    pub delegate_record: Option<Account<'info, DelegateRecord>>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        token::mint = user_stake.position_mint,
+        token::authority = user,
+    )]
+    pub position_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct SetBoostSources<'info> {
+    #[account(mut, has_one = authority)]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetReferralBps<'info> {
+    #[account(mut, has_one = authority)]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetGovernanceAuthority<'info> {
+    #[account(mut, has_one = authority)]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePoolParams<'info> {
+    #[account(
+        mut,
+        constraint = authority.key() == staking_pool.authority
+            || (staking_pool.governance_authority != Pubkey::default()
+                && authority.key() == staking_pool.governance_authority)
+            @ ErrorCode::Unauthorized,
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    // Needed to bound a new epoch reward budget by the reward reserve
+    #[account(
+        seeds = [b"vault", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub vault: Option<Account<'info, TokenAccount>>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreateStakingTier<'info> {
+    #[account(
+        mut,
+        constraint = authority.key() == staking_pool.authority
+            || (staking_pool.governance_authority != Pubkey::default()
+                && authority.key() == staking_pool.governance_authority)
+            @ ErrorCode::Unauthorized,
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + StakingTier::INIT_SPACE
+    )]
+    pub staking_tier: Account<'info, StakingTier>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct StakeTokens<'info> {
+    #[account(
+        mut,
+        constraint = !staking_pool.is_paused @ ErrorCode::PoolPaused,
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    
+    #[account(mut)]
+    pub staking_tier: Account<'info, StakingTier>,
+    
+    #[account(
+        init,
+        payer = user,
+        space = 8 + UserStake::INIT_SPACE,
+        seeds = [b"user_stake", user.key().as_ref(), staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub user_stake: Account<'info, UserStake>,
+    
+    #[account(mut)]
+    pub vault: Account<'info, TokenAccount>,
+    
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+
+    // Optional referral: the referrer's stake and the Referral to record
+    #[account(
+        constraint = referrer_stake.pool == staking_pool.key() @ ErrorCode::InvalidReferrer,
+    )]
+    pub referrer_stake: Option<Account<'info, UserStake>>,
+
+    #[account(
+        init,
+        payer = user,
+        space = 8 + Referral::INIT_SPACE,
+        seeds = [b"referral", staking_pool.key().as_ref(), user.key().as_ref()],
+        bump,
+    )]
+    pub referral: Option<Account<'info, Referral>>,
+}
+
+#[derive(Accounts)]
+#[instruction(beneficiary: Pubkey)]
+pub struct StakeFor<'info> {
+    #[account(
+        mut,
+        constraint = !staking_pool.is_paused @ ErrorCode::PoolPaused,
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        constraint = staking_tier.pool == staking_pool.key() @ ErrorCode::InvalidStakingTier,
+    )]
+    pub staking_tier: Account<'info, StakingTier>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + UserStake::INIT_SPACE,
+        seeds = [
+            b"vested_stake",
+            beneficiary.as_ref(),
+            staking_pool.key().as_ref(),
+            payer.key().as_ref(),
+        ],
+        bump,
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub payer_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimReferralRewards<'info> {
+    #[account(
+        mut,
+        constraint = !staking_pool.is_paused @ ErrorCode::PoolPaused,
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        seeds = [b"referral", staking_pool.key().as_ref(), referral.referee.as_ref()],
+        bump,
+        has_one = referrer,
+    )]
+    pub referral: Account<'info, Referral>,
+
+    #[account(
+        mut,
+        seeds = [b"user_stake", referral.referee.as_ref(), staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub referrer_token_account: Account<'info, TokenAccount>,
+
+    pub referrer: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct UnstakeTokens<'info> {
+    #[account(
+        mut,
+        constraint = !staking_pool.is_paused @ ErrorCode::PoolPaused,
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    
+    #[account(
+        mut,
+        address = user_stake.tier @ ErrorCode::InvalidStakingTier,
+    )]
+    pub staking_tier: Account<'info, StakingTier>,
+    
+    #[account(
+        mut,
+        constraint = user_stake.pool == staking_pool.key() @ ErrorCode::StakePoolMismatch,
+    )]
+    pub user_stake: Account<'info, UserStake>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        address = staking_pool.token_mint,
+    )]
+    pub token_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        address = staking_pool.penalty_treasury @ ErrorCode::PenaltyTreasuryRequired,
+    )]
+    pub penalty_treasury: Option<Account<'info, TokenAccount>>,
+    
+    #[account(
+        token::mint = user_stake.position_mint,
+        token::authority = user,
+    )]
+    pub position_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureUnbonding<'info> {
+    #[account(mut, has_one = authority)]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureEpochRewards<'info> {
+    #[account(mut, has_one = authority)]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AdvanceEpoch<'info> {
+    #[account(mut)]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + EpochRecord::INIT_SPACE,
+        seeds = [
+            b"epoch_record",
+            staking_pool.key().as_ref(),
+            staking_pool.current_reward_epoch.to_le_bytes().as_ref(),
+        ],
+        bump,
+    )]
+    pub epoch_record: Account<'info, EpochRecord>,
+
+    #[account(
+        seeds = [b"vault", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimEpochRewards<'info> {
+    #[account(
+        mut,
+        constraint = !staking_pool.is_paused @ ErrorCode::PoolPaused,
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"epoch_record",
+            staking_pool.key().as_ref(),
+            user_stake.next_claim_epoch.to_le_bytes().as_ref(),
+        ],
+        bump,
+    )]
+    pub epoch_record: Account<'info, EpochRecord>,
+
+    #[account(
+        mut,
+        constraint = user_stake.pool == staking_pool.key() @ ErrorCode::StakePoolMismatch,
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        token::mint = user_stake.position_mint,
+        token::authority = user,
+    )]
+    pub position_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigurePenaltyPolicy<'info> {
+    #[account(mut, has_one = authority)]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(token::mint = staking_pool.token_mint)]
+    pub penalty_treasury: Option<Account<'info, TokenAccount>>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RequestUnstake<'info> {
+    #[account(mut)]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        address = user_stake.tier @ ErrorCode::InvalidStakingTier,
+    )]
+    pub staking_tier: Account<'info, StakingTier>,
+
+    #[account(
+        mut,
+        constraint = user_stake.pool == staking_pool.key() @ ErrorCode::StakePoolMismatch,
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    #[account(
+        token::mint = user_stake.position_mint,
+        token::authority = user,
+    )]
+    pub position_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawUnbonded<'info> {
+    #[account(
+        mut,
+        constraint = !staking_pool.is_paused @ ErrorCode::PoolPaused,
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(address = user_stake.tier @ ErrorCode::InvalidStakingTier)]
+    pub staking_tier: Account<'info, StakingTier>,
+
+    #[account(
+        mut,
+        constraint = user_stake.pool == staking_pool.key() @ ErrorCode::StakePoolMismatch,
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        token::mint = user_stake.position_mint,
+        token::authority = user,
+    )]
+    pub position_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimRewards<'info> {
+    #[account(
+        mut,
+        constraint = !staking_pool.is_paused @ ErrorCode::PoolPaused,
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    
+    #[account(address = user_stake.tier @ ErrorCode::InvalidStakingTier)]
+    pub staking_tier: Account<'info, StakingTier>,
+    
+    #[account(
+        mut,
+        constraint = user_stake.pool == staking_pool.key() @ ErrorCode::StakePoolMismatch,
+    )]
+    pub user_stake: Account<'info, UserStake>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
     
+    #[account(
+        token::mint = user_stake.position_mint,
+        token::authority = user,
+    )]
+    pub position_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ExtendStake<'info> {
+    #[account(
+        mut,
+        constraint = !staking_pool.is_paused @ ErrorCode::PoolPaused,
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        address = user_stake.tier @ ErrorCode::InvalidStakingTier,
+    )]
+    pub staking_tier: Account<'info, StakingTier>,
+
+    #[account(
+        mut,
+        constraint = new_staking_tier.pool == staking_pool.key() @ ErrorCode::InvalidStakingTier,
+    )]
+    pub new_staking_tier: Account<'info, StakingTier>,
+
+    #[account(
+        mut,
+        constraint = user_stake.pool == staking_pool.key() @ ErrorCode::StakePoolMismatch,
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        token::mint = user_stake.position_mint,
+        token::authority = user,
+    )]
+    pub position_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct AddToStake<'info> {
+    #[account(
+        mut,
+        constraint = !staking_pool.is_paused @ ErrorCode::PoolPaused,
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        address = user_stake.tier @ ErrorCode::InvalidStakingTier,
+    )]
+    pub staking_tier: Account<'info, StakingTier>,
+
+    #[account(
+        mut,
+        constraint = user_stake.pool == staking_pool.key() @ ErrorCode::StakePoolMismatch,
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        token::mint = user_stake.position_mint,
+        token::authority = user,
+    )]
+    pub position_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct PartialUnstake<'info> {
+    #[account(
+        mut,
+        constraint = !staking_pool.is_paused @ ErrorCode::PoolPaused,
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        address = user_stake.tier @ ErrorCode::InvalidStakingTier,
+    )]
+    pub staking_tier: Account<'info, StakingTier>,
+
+    #[account(
+        mut,
+        constraint = user_stake.pool == staking_pool.key() @ ErrorCode::StakePoolMismatch,
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        address = staking_pool.token_mint,
+    )]
+    pub token_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        address = staking_pool.penalty_treasury @ ErrorCode::PenaltyTreasuryRequired,
+    )]
+    pub penalty_treasury: Option<Account<'info, TokenAccount>>,
+
+    #[account(
+        token::mint = user_stake.position_mint,
+        token::authority = user,
+    )]
+    pub position_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct EnableLiquidStaking<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+        has_one = token_mint,
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        constraint = liquid_tier.pool == staking_pool.key() @ ErrorCode::InvalidStakingTier,
+    )]
+    pub liquid_tier: Account<'info, StakingTier>,
+
+    pub token_mint: Account<'info, Mint>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"receipt_mint", staking_pool.key().as_ref()],
+        bump,
+        mint::decimals = token_mint.decimals,
+        mint::authority = receipt_mint,
+    )]
+    pub receipt_mint: Account<'info, Mint>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"liquid_vault", staking_pool.key().as_ref()],
+        bump,
+        token::mint = token_mint,
+        token::authority = liquid_vault,
+    )]
+    pub liquid_vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct LiquidStake<'info> {
+    #[account(
+        mut,
+        constraint = !staking_pool.is_paused @ ErrorCode::PoolPaused,
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(mut, address = staking_pool.liquid_tier @ ErrorCode::LiquidStakingNotEnabled)]
+    pub liquid_tier: Account<'info, StakingTier>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"liquid_vault", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub liquid_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"receipt_mint", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub receipt_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        token::mint = receipt_mint,
+    )]
+    pub user_receipt_account: Account<'info, TokenAccount>,
+
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct LiquidUnstake<'info> {
+    #[account(mut)]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(mut, address = staking_pool.liquid_tier @ ErrorCode::LiquidStakingNotEnabled)]
+    pub liquid_tier: Account<'info, StakingTier>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"liquid_vault", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub liquid_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"receipt_mint", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub receipt_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        token::mint = receipt_mint,
+        token::authority = user,
+    )]
+    pub user_receipt_account: Account<'info, TokenAccount>,
+
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CreateLock<'info> {
+    #[account(
+        mut,
+        constraint = !staking_pool.is_paused @ ErrorCode::PoolPaused,
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        init,
+        payer = user,
+        space = 8 + VoteEscrow::INIT_SPACE,
+        seeds = [b"vote_escrow", user.key().as_ref(), staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub vote_escrow: Account<'info, VoteEscrow>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ModifyLock<'info> {
+    #[account(
+        mut,
+        constraint = !staking_pool.is_paused @ ErrorCode::PoolPaused,
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        seeds = [b"vote_escrow", user.key().as_ref(), staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub vote_escrow: Account<'info, VoteEscrow>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ExtendLock<'info> {
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        seeds = [b"vote_escrow", user.key().as_ref(), staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub vote_escrow: Account<'info, VoteEscrow>,
+
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawEscrow<'info> {
+    #[account(mut)]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        close = user,
+        seeds = [b"vote_escrow", user.key().as_ref(), staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub vote_escrow: Account<'info, VoteEscrow>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct GetVotingPower<'info> {
+    pub vote_escrow: Account<'info, VoteEscrow>,
+}
+
+#[derive(Accounts)]
+pub struct TokenizeStake<'info> {
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        constraint = user_stake.pool == staking_pool.key() @ ErrorCode::StakePoolMismatch,
+        constraint = user_stake.user == user.key() @ ErrorCode::Unauthorized,
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    #[account(
+        init,
+        payer = user,
+        seeds = [b"position_mint", user_stake.key().as_ref()],
+        bump,
+        mint::decimals = 0,
+        mint::authority = position_mint,
+    )]
+    pub position_mint: Account<'info, Mint>,
+
+    #[account(
+        init,
+        payer = user,
+        associated_token::mint = position_mint,
+        associated_token::authority = user,
+    )]
+    pub user_position_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
     pub system_program: Program<'info, System>,
     pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
     pub rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
-pub struct CreateStakingTier<'info> {
-    #[account(mut)]
+pub struct AddRewardToken<'info> {
+    #[account(mut, has_one = authority)]
     pub staking_pool: Account<'info, StakingPool>,
-    
+
+    pub reward_mint: Account<'info, Mint>,
+
     #[account(
         init,
         payer = authority,
-        space = 8 + StakingTier::INIT_SPACE
+        seeds = [b"reward_vault", staking_pool.key().as_ref(), reward_mint.key().as_ref()],
+        bump,
+        token::mint = reward_mint,
+        token::authority = reward_vault,
     )]
-    pub staking_tier: Account<'info, StakingTier>,
-    
+    pub reward_vault: Account<'info, TokenAccount>,
+
     #[account(mut)]
     pub authority: Signer<'info>,
-    
+
     pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
-pub struct StakeTokens<'info> {
+pub struct DepositRewards<'info> {
     #[account(mut)]
     pub staking_pool: Account<'info, StakingPool>,
-    
-    #[account(mut)]
-    pub staking_tier: Account<'info, StakingTier>,
-    
+
     #[account(
-        init,
-        payer = user,
-        space = 8 + UserStake::INIT_SPACE,
-        seeds = [b"user_stake", user.key().as_ref(), staking_pool.key().as_ref()],
+        mut,
+        seeds = [b"reward_vault", staking_pool.key().as_ref(), reward_vault.mint.as_ref()],
         bump,
     )]
-    pub user_stake: Account<'info, UserStake>,
-    
-    #[account(mut)]
-    pub vault: Account<'info, TokenAccount>,
-    
-    #[account(mut)]
-    pub user_token_account: Account<'info, TokenAccount>,
-    
+    pub reward_vault: Account<'info, TokenAccount>,
+
     #[account(mut)]
-    pub user: Signer<'info>,
-    
-    pub system_program: Program<'info, System>,
+    pub depositor_token_account: Account<'info, TokenAccount>,
+
+    pub depositor: Signer<'info>,
     pub token_program: Program<'info, Token>,
 }
 
 #[derive(Accounts)]
-pub struct UnstakeTokens<'info> {
-    #[account(mut)]
+pub struct ClaimRewardToken<'info> {
+    #[account(
+        mut,
+        constraint = !staking_pool.is_paused @ ErrorCode::PoolPaused,
+    )]
     pub staking_pool: Account<'info, StakingPool>,
-    
-    #[account(mut)]
-    pub staking_tier: Account<'info, StakingTier>,
-    
+
     #[account(
         mut,
-        seeds = [b"user_stake", user.key().as_ref(), staking_pool.key().as_ref()],
-        bump,
+        constraint = user_stake.pool == staking_pool.key() @ ErrorCode::StakePoolMismatch,
     )]
     pub user_stake: Account<'info, UserStake>,
-    
+
     #[account(
         mut,
-        seeds = [b"vault", staking_pool.key().as_ref()],
+        seeds = [b"reward_vault", staking_pool.key().as_ref(), reward_vault.mint.as_ref()],
         bump,
     )]
-    pub vault: Account<'info, TokenAccount>,
-    
-    #[account(mut)]
-    pub user_token_account: Account<'info, TokenAccount>,
-    
+    pub reward_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        token::mint = reward_vault.mint,
+    )]
+    pub user_reward_account: Account<'info, TokenAccount>,
+
+    #[account(
+        token::mint = user_stake.position_mint,
+        token::authority = user,
+    )]
+    pub position_token_account: Option<Account<'info, TokenAccount>>,
+
     pub user: Signer<'info>,
     pub token_program: Program<'info, Token>,
 }
 
 #[derive(Accounts)]
-pub struct ClaimRewards<'info> {
+#[instruction(delegate: Pubkey)]
+pub struct CreateDelegateRecord<'info> {
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + DelegateRecord::INIT_SPACE,
+        seeds = [b"delegate_record", staking_pool.key().as_ref(), delegate.as_ref()],
+        bump,
+    )]
+    pub delegate_record: Account<'info, DelegateRecord>,
+
     #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DelegateStakeVotingPower<'info> {
     pub staking_pool: Account<'info, StakingPool>,
-    
-    pub staking_tier: Account<'info, StakingTier>,
-    
+
     #[account(
         mut,
-        seeds = [b"user_stake", user.key().as_ref(), staking_pool.key().as_ref()],
-        bump,
+        constraint = user_stake.pool == staking_pool.key() @ ErrorCode::StakePoolMismatch,
     )]
     pub user_stake: Account<'info, UserStake>,
-    
+
     #[account(
         mut,
-        seeds = [b"vault", staking_pool.key().as_ref()],
+        seeds = [b"delegate_record", staking_pool.key().as_ref(), delegate_record.delegate.as_ref()],
         bump,
     )]
-    pub vault: Account<'info, TokenAccount>,
-    
+    pub delegate_record: Account<'info, DelegateRecord>,
+
+    #[account(
+        mut,
+        seeds = [b"delegate_record", staking_pool.key().as_ref(), user_stake.delegate.as_ref()],
+        bump,
+    )]
+    pub previous_delegate_record: Option<Account<'info, DelegateRecord>>,
+
+    #[account(
+        token::mint = user_stake.position_mint,
+        token::authority = user,
+    )]
+    pub position_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct LockStakeForVote<'info> {
     #[account(mut)]
-    pub user_token_account: Account<'info, TokenAccount>,
-    
+    pub user_stake: Account<'info, UserStake>,
+
+    #[account(
+        token::mint = user_stake.position_mint,
+        token::authority = user,
+    )]
+    pub position_token_account: Option<Account<'info, TokenAccount>>,
+
     pub user: Signer<'info>,
-    pub token_program: Program<'info, Token>,
 }
 
-#[account]
-#[derive(InitSpace)]
-pub struct StakingPool {
-    pub authority: Pubkey,
-    pub token_mint: Pubkey,
-    pub vault: Pubkey,
-    pub early_unstake_penalty: u16, // basis points
-    pub total_staked: u64,
-    pub total_rewards_distributed: u64,
-    pub is_initialized: bool,
+#[derive(Accounts)]
+pub struct LockDelegateRecordForVote<'info> {
+    #[account(
+        mut,
+        constraint = delegate_record.delegate == delegate.key() @ ErrorCode::InvalidDelegate,
+    )]
+    pub delegate_record: Account<'info, DelegateRecord>,
+
+    pub delegate: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UndelegateStakeVotingPower<'info> {
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        constraint = user_stake.pool == staking_pool.key() @ ErrorCode::StakePoolMismatch,
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    #[account(
+        mut,
+        seeds = [b"delegate_record", staking_pool.key().as_ref(), user_stake.delegate.as_ref()],
+        bump,
+    )]
+    pub delegate_record: Account<'info, DelegateRecord>,
+
+    #[account(
+        token::mint = user_stake.position_mint,
+        token::authority = user,
+    )]
+    pub position_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub user: Signer<'info>,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct StakingPool {
+    pub authority: Pubkey,
+    pub token_mint: Pubkey,
+    pub vault: Pubkey,
+    pub early_unstake_penalty: u16, // basis points
+    pub total_staked: u64,
+    pub total_rewards_distributed: u64,
+    pub is_initialized: bool,
+    pub liquid_tier: Pubkey,
+    pub receipt_mint: Pubkey,
+    pub liquid_vault: Pubkey,
+    pub liquid_last_accrual: i64,
+    // Principal in the liquid vault, included in total_staked
+    pub liquid_staked: u64,
+    pub unstake_mode: UnstakeMode,
+    pub unbonding_period: i64,
+    pub penalty_destination: PenaltyDestination,
+    pub penalty_treasury: Pubkey,
+    pub total_penalties_collected: u64,
+    pub acc_reward_per_share: u128,
+    pub total_unbonding: u64,
+    pub accumulated_rewards_outstanding: u64,
+    pub total_escrowed: u64,
+    #[max_len(MAX_REWARD_TOKENS)]
+    pub reward_tokens: Vec<RewardToken>,
+    pub is_paused: bool,
+    pub guardian: Pubkey,
+    #[max_len(MAX_BOOST_SOURCES)]
+    pub boost_sources: Vec<BoostSource>,
+    pub total_effective_stake: u64,
+    pub governance_authority: Pubkey,
+    pub reward_mode: RewardMode,
+    pub epoch_duration: i64,
+    pub epoch_reward_budget: u64,
+    pub current_reward_epoch: u64,
+    pub epoch_start_time: i64,
+    pub epoch_rewards_outstanding: u64,
+    // Sum of epoch_weight over all stakes for the epoch in progress
+    pub epoch_eligible_stake: u64,
+    pub referral_bps: u16,
+    pub referral_rewards_outstanding: u64,
+}
+
+impl StakingPool {
+    // Principal held in the pool vault for individual stakes.
+    pub fn vault_staked(&self) -> u64 {
+        self.total_staked - self.liquid_staked
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
+pub struct RewardToken {
+    pub mint: Pubkey,
+    pub vault: Pubkey,
+    pub acc_reward_per_share: u128,
+    pub total_deposited: u64,
+    pub undistributed: u64,
+}
+
+impl RewardToken {
+    // Spreads `amount`, plus anything held back earlier, over the pool's
+    // effective stake. Deposits made while nobody is staked are held until
+    // there is someone to pay.
+    fn distribute(&mut self, amount: u64, total_effective_stake: u64) {
+        if total_effective_stake == 0 {
+            self.undistributed += amount;
+        } else {
+            let distributable = amount + self.undistributed;
+            self.acc_reward_per_share +=
+                distributable as u128 * ACC_PRECISION / total_effective_stake as u128;
+            self.undistributed = 0;
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub struct PoolParams {
+    pub early_unstake_penalty: Option<u16>, // basis points
+    pub unbonding_period: Option<i64>,
+    pub tier_apy_basis_points: Vec<u16>,
+    pub epoch_reward_budget: Option<u64>,
+    pub referral_bps: Option<u16>,
+    // One multiplier per configured boost source, in order
+    pub boost_multipliers: Option<Vec<u16>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
+pub struct BoostSource {
+    pub kind: BoostKind,
+    pub multiplier_bps: u16,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum BoostKind {
+    NftCollection { collection: Pubkey },
+    Loyalty { min_epochs: u64 },
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct StakingTier {
+    pub pool: Pubkey,
+    pub duration_days: u32,
+    pub apy_basis_points: u16,
+    pub total_staked: u64,
+    pub is_active: bool,
+    // Rates that applied before each APY change, oldest first
+    #[max_len(MAX_APY_CHANGES)]
+    pub apy_history: Vec<ApyPeriod>,
+}
+
+impl StakingTier {
+    /// Sum of APY basis points over each second in `[from, to)`, using the
+    /// rate in effect during each second.
+    pub fn apy_seconds_between(&self, from: i64, to: i64) -> u128 {
+        let mut total = 0u128;
+        let mut cursor = from;
+        for period in self.apy_history.iter() {
+            if cursor >= to {
+                return total;
+            }
+            if period.until > cursor {
+                let period_end = std::cmp::min(period.until, to);
+                total += period.apy_basis_points as u128 * (period_end - cursor) as u128;
+                cursor = period_end;
+            }
+        }
+        if to > cursor {
+            total += self.apy_basis_points as u128 * (to - cursor) as u128;
+        }
+        total
+    }
+
+    // Closes the current rate's period so it keeps applying to time already
+    // accrued. When the history is full the two oldest periods merge at the
+    // lower rate, which can only under-pay that stretch.
+    fn set_apy(&mut self, apy_basis_points: u16, current_time: i64) {
+        if apy_basis_points == self.apy_basis_points {
+            return;
+        }
+        let previous = ApyPeriod {
+            until: current_time,
+            apy_basis_points: self.apy_basis_points,
+        };
+        match self.apy_history.last_mut() {
+            // A rate set and replaced within one timestamp never applied
+            Some(last) if last.until == current_time => {}
+            _ => {
+                if self.apy_history.len() == MAX_APY_CHANGES {
+                    let oldest = self.apy_history.remove(0);
+                    let next = &mut self.apy_history[0];
+                    next.apy_basis_points =
+                        std::cmp::min(next.apy_basis_points, oldest.apy_basis_points);
+                }
+                self.apy_history.push(previous);
+            }
+        }
+        self.apy_basis_points = apy_basis_points;
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub struct ApyPeriod {
+    pub until: i64,
+    pub apy_basis_points: u16,
+}
+
+// Stakes live at [b"user_stake", user, pool], or at
+// [b"vested_stake", beneficiary, pool, payer] when opened through stake_for,
+// so instructions match a stake to its pool instead of re-deriving it.
+#[account]
+#[derive(InitSpace)]
+pub struct UserStake {
+    pub user: Pubkey,
+    pub pool: Pubkey,
+    pub tier: Pubkey,
+    pub amount: u64,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub rewards_claimed: u64,
+    pub is_active: bool,
+    pub is_unbonding: bool,
+    pub unbonding_start: i64,
+    pub unbonding_end: i64,
+    pub reward_debt: u128,
+    pub accumulated_rewards: u64,
+    pub delegate: Pubkey,
+    pub position_mint: Pubkey,
+    pub reward_token_debts: [u128; MAX_REWARD_TOKENS],
+    pub reward_token_pending: [u64; MAX_REWARD_TOKENS],
+    pub boost_bps: u16,
+    pub effective_amount: u64,
+    pub staked_since_epoch: u64,
+    pub next_claim_epoch: u64,
+    pub epoch_amount_from: u64,
+    pub prior_epoch_amount: u64,
+    pub vest_locked: bool,
+    pub referrer: Pubkey,
+    pub referral_rewards_owed: u64,
+    // Set by governance while the stake backs a vote; exits wait until after it
+    pub vote_locked_until: i64,
+}
+
+impl UserStake {
+    /// Whether the stake may leave early or change hands at `timestamp`: a
+    /// stake made with `locked_until_vest` is held until its lock ends.
+    pub fn vest_unlocked_at(&self, timestamp: i64) -> bool {
+        !self.vest_locked || timestamp >= self.end_time
+    }
+
+    /// Balance held through the whole of reward epoch `epoch`.
+    pub fn epoch_weight(&self, epoch: u64) -> u64 {
+        if epoch >= self.epoch_amount_from {
+            self.amount
+        } else {
+            self.prior_epoch_amount
+        }
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct DelegateRecord {
+    pub pool: Pubkey,
+    pub delegate: Pubkey,
+    pub delegated_power: u64,
+    pub delegator_count: u32,
+    // Set by governance while the delegate has voted with this power;
+    // delegators cannot move their stake away until after it. Together with
+    // the stake's own vote lock this keeps a stake from counting twice on a
+    // proposal, so governance can read delegated_power as it stands.
+    pub vote_locked_until: i64,
+}
+
+impl DelegateRecord {
+    fn add_power(&mut self, power: u64) {
+        self.delegated_power += power;
+        self.delegator_count += 1;
+    }
+
+    fn remove_power(&mut self, power: u64) {
+        self.delegated_power -= power;
+        self.delegator_count -= 1;
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct VoteEscrow {
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+    pub amount: u64,
+    pub lock_start: i64,
+    pub unlock_time: i64,
+    // Earliest timestamp the retained checkpoints can answer for; non-zero
+    // once old checkpoints have been dropped
+    pub history_start: i64,
+    #[max_len(MAX_ESCROW_CHECKPOINTS)]
+    pub checkpoints: Vec<EscrowCheckpoint>,
+}
+
+impl VoteEscrow {
+    /// Voting power at `timestamp`: the amount locked at that time scaled by
+    /// its remaining lock over the maximum lock, decaying linearly to zero at
+    /// the unlock time then in effect. Later `increase_amount` and
+    /// `extend_lock` calls do not change the power reported for earlier
+    /// timestamps. Errors if `timestamp` predates the retained checkpoints.
+    pub fn voting_power_at(&self, timestamp: i64) -> Result<u64> {
+        if timestamp < self.lock_start {
+            return Ok(0);
+        }
+        require!(
+            timestamp >= self.history_start,
+            ErrorCode::CheckpointUnavailable
+        );
+        let Some(checkpoint) = self
+            .checkpoints
+            .iter()
+            .rev()
+            .find(|checkpoint| checkpoint.timestamp <= timestamp)
+        else {
+            return Ok(0);
+        };
+        if timestamp >= checkpoint.unlock_time {
+            return Ok(0);
+        }
+        let remaining = (checkpoint.unlock_time - timestamp) as u128;
+        Ok((checkpoint.amount as u128 * remaining / MAX_LOCK_DURATION as u128) as u64)
+    }
+
+    fn write_checkpoint(&mut self, timestamp: i64) {
+        let checkpoint = EscrowCheckpoint {
+            timestamp,
+            amount: self.amount,
+            unlock_time: self.unlock_time,
+        };
+        if let Some(last) = self.checkpoints.last_mut() {
+            if last.timestamp == timestamp {
+                *last = checkpoint;
+                return;
+            }
+        }
+        if self.checkpoints.len() == MAX_ESCROW_CHECKPOINTS {
+            self.checkpoints.remove(0);
+            self.history_start = self.checkpoints[0].timestamp;
+        }
+        self.checkpoints.push(checkpoint);
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub struct EscrowCheckpoint {
+    pub timestamp: i64,
+    pub amount: u64,
+    pub unlock_time: i64,
 }
 
 #[account]
 #[derive(InitSpace)]
-pub struct StakingTier {
+pub struct Referral {
     pub pool: Pubkey,
-    pub duration_days: u32,
-    pub apy_basis_points: u16,
-    pub total_staked: u64,
-    pub is_active: bool,
+    pub referrer: Pubkey,
+    pub referee: Pubkey,
+    pub total_claimed: u64,
 }
 
 #[account]
 #[derive(InitSpace)]
-pub struct UserStake {
-    pub user: Pubkey,
+pub struct EpochRecord {
     pub pool: Pubkey,
-    pub tier: Pubkey,
-    pub amount: u64,
+    pub epoch: u64,
     pub start_time: i64,
     pub end_time: i64,
+    pub total_staked: u64,
+    // Combined weight of the stakes held through the epoch; the budget is
+    // split over this rather than total_staked so mid-epoch joiners, who
+    // earn nothing for it, do not dilute the shares
+    pub eligible_stake: u64,
+    pub reward_budget: u64,
     pub rewards_claimed: u64,
-    pub is_active: bool,
+}
+
+impl EpochRecord {
+    /// Budget share for a stake that held `weight` through the epoch.
+    pub fn share_of(&self, weight: u64) -> u64 {
+        if self.eligible_stake == 0 {
+            return 0;
+        }
+        let share =
+            weight as u128 * self.reward_budget as u128 / self.eligible_stake as u128;
+        std::cmp::min(share as u64, self.reward_budget - self.rewards_claimed)
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum RewardMode {
+    Continuous,
+    Epoch,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum UnstakeMode {
+    Penalty,
+    Cooldown,
+    PenaltyOrCooldown,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum PenaltyDestination {
+    Burn,
+    Treasury,
+    Redistribute,
+}
+
+#[event]
+pub struct PenaltyCollected {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub destination: PenaltyDestination,
 }
 
 #[error_code]
@@ -365,4 +3841,1127 @@ pub enum ErrorCode {
     NoRewardsAvailable,
     #[msg("Invalid staking tier")]
     InvalidStakingTier,
+    #[msg("Invalid amount")]
+    InvalidAmount,
+    #[msg("Liquid staking is already enabled")]
+    LiquidStakingAlreadyEnabled,
+    #[msg("Liquid staking is not enabled")]
+    LiquidStakingNotEnabled,
+    #[msg("Slippage tolerance exceeded")]
+    SlippageExceeded,
+    #[msg("Math overflow")]
+    MathOverflow,
+    #[msg("Stake is unbonding")]
+    StakeUnbonding,
+    #[msg("Stake is not unbonding")]
+    StakeNotUnbonding,
+    #[msg("Unbonding period not complete")]
+    UnbondingPeriodNotComplete,
+    #[msg("Unbonding cooldown is not enabled for this pool")]
+    CooldownNotEnabled,
+    #[msg("Early unstake penalty is disabled for this pool")]
+    EarlyUnstakePenaltyDisabled,
+    #[msg("Invalid unbonding period")]
+    InvalidUnbondingPeriod,
+    #[msg("Penalty treasury account required")]
+    PenaltyTreasuryRequired,
+    #[msg("Invalid lock duration")]
+    InvalidLockDuration,
+    #[msg("Lock has expired")]
+    LockExpired,
+    #[msg("Lock has not expired")]
+    LockNotExpired,
+    #[msg("Stake voting power is delegated")]
+    StakeDelegated,
+    #[msg("Stake voting power is not delegated")]
+    StakeNotDelegated,
+    #[msg("Stake is already delegated to this delegate")]
+    AlreadyDelegated,
+    #[msg("Invalid delegate")]
+    InvalidDelegate,
+    #[msg("Unauthorized")]
+    Unauthorized,
+    #[msg("Stake is already tokenized")]
+    StakeAlreadyTokenized,
+    #[msg("Position token account required")]
+    PositionTokenRequired,
+    #[msg("Too many reward tokens")]
+    TooManyRewardTokens,
+    #[msg("Reward token already added")]
+    RewardTokenAlreadyAdded,
+    #[msg("Invalid reward token")]
+    InvalidRewardToken,
+    #[msg("Staking pool is paused")]
+    PoolPaused,
+    #[msg("Staking pool is not paused")]
+    PoolNotPaused,
+    #[msg("Invalid boost sources")]
+    InvalidBoostSources,
+    #[msg("Parameter out of bounds")]
+    ParameterOutOfBounds,
+    #[msg("Epoch duration is too short")]
+    InvalidEpochDuration,
+    #[msg("Pool must have no stakes to change reward mode")]
+    PoolNotEmpty,
+    #[msg("Epoch rewards are not enabled for this pool")]
+    EpochRewardsNotEnabled,
+    #[msg("Reward epoch has not ended yet")]
+    EpochNotComplete,
+    #[msg("Claim finalized epoch rewards before changing the stake")]
+    EpochRewardsUnclaimed,
+    #[msg("Invalid beneficiary")]
+    InvalidBeneficiary,
+    #[msg("Stake is locked until it vests")]
+    StakeVestLocked,
+    #[msg("Invalid referrer")]
+    InvalidReferrer,
+    #[msg("Referrer stake and referral account must be given together")]
+    ReferralAccountRequired,
+    #[msg("Timestamp predates the retained checkpoints")]
+    CheckpointUnavailable,
+    #[msg("Stake belongs to another pool")]
+    StakePoolMismatch,
+    #[msg("Stake backs a live governance vote")]
+    StakeVoteLocked,
+    #[msg("Epoch record is not the stake's next unclaimed epoch")]
+    InvalidEpochRecord,
+    #[msg("Staking vault account is required")]
+    VaultRequired,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::solana_program::program_pack::Pack;
+    use anchor_spl::token::spl_token::state::{
+        Account as SplTokenAccount, AccountState, Mint as SplMint,
+    };
+    use std::collections::BTreeSet;
+
+    fn token_account(mint: Pubkey, owner: Pubkey, amount: u64) -> TokenAccount {
+        let mut data = [0u8; SplTokenAccount::LEN];
+        SplTokenAccount {
+            mint,
+            owner,
+            amount,
+            state: AccountState::Initialized,
+            ..Default::default()
+        }
+        .pack_into_slice(&mut data);
+        TokenAccount::try_deserialize_unchecked(&mut &data[..]).unwrap()
+    }
+
+    fn user_stake(user: Pubkey, position_mint: Pubkey) -> UserStake {
+        UserStake {
+            user,
+            pool: Pubkey::new_unique(),
+            tier: Pubkey::new_unique(),
+            amount: 1_000,
+            start_time: 0,
+            end_time: 0,
+            rewards_claimed: 0,
+            is_active: true,
+            is_unbonding: false,
+            unbonding_start: 0,
+            unbonding_end: 0,
+            reward_debt: 0,
+            accumulated_rewards: 0,
+            delegate: Pubkey::default(),
+            position_mint,
+            reward_token_debts: [0; MAX_REWARD_TOKENS],
+            reward_token_pending: [0; MAX_REWARD_TOKENS],
+            boost_bps: 0,
+            effective_amount: 0,
+            staked_since_epoch: 0,
+            next_claim_epoch: 0,
+            epoch_amount_from: 0,
+            prior_epoch_amount: 0,
+            vest_locked: false,
+            referrer: Pubkey::default(),
+            referral_rewards_owed: 0,
+            vote_locked_until: 0,
+        }
+    }
+
+    fn staking_pool(token_mint: Pubkey) -> StakingPool {
+        StakingPool {
+            authority: Pubkey::new_unique(),
+            token_mint,
+            vault: Pubkey::default(),
+            early_unstake_penalty: 0,
+            total_staked: 1_000,
+            total_rewards_distributed: 0,
+            is_initialized: true,
+            liquid_tier: Pubkey::default(),
+            receipt_mint: Pubkey::default(),
+            liquid_vault: Pubkey::default(),
+            liquid_last_accrual: 0,
+            liquid_staked: 0,
+            unstake_mode: UnstakeMode::Penalty,
+            unbonding_period: 0,
+            penalty_destination: PenaltyDestination::Burn,
+            penalty_treasury: Pubkey::default(),
+            total_penalties_collected: 0,
+            acc_reward_per_share: 0,
+            total_unbonding: 0,
+            accumulated_rewards_outstanding: 0,
+            total_escrowed: 0,
+            reward_tokens: Vec::new(),
+            is_paused: false,
+            guardian: Pubkey::default(),
+            boost_sources: Vec::new(),
+            total_effective_stake: 1_000,
+            governance_authority: Pubkey::default(),
+            reward_mode: RewardMode::Continuous,
+            epoch_duration: 0,
+            epoch_reward_budget: 0,
+            current_reward_epoch: 0,
+            epoch_start_time: 0,
+            epoch_rewards_outstanding: 0,
+            epoch_eligible_stake: 0,
+            referral_bps: 0,
+            referral_rewards_outstanding: 0,
+        }
+    }
+
+    // Backing storage for an AccountInfo handed to an Accounts context
+    struct TestAccount {
+        key: Pubkey,
+        owner: Pubkey,
+        lamports: u64,
+        data: Vec<u8>,
+        is_signer: bool,
+        executable: bool,
+    }
+
+    impl TestAccount {
+        fn new(key: Pubkey, owner: Pubkey, data: Vec<u8>) -> Self {
+            TestAccount {
+                key,
+                owner,
+                lamports: 1_000_000,
+                data,
+                is_signer: false,
+                executable: false,
+            }
+        }
+
+        fn program<T: AccountSerialize>(key: Pubkey, account: &T) -> Self {
+            let mut data = Vec::new();
+            account.try_serialize(&mut data).unwrap();
+            Self::new(key, crate::ID, data)
+        }
+
+        fn token(key: Pubkey, mint: Pubkey, owner: Pubkey, amount: u64) -> Self {
+            let mut data = vec![0u8; SplTokenAccount::LEN];
+            SplTokenAccount {
+                mint,
+                owner,
+                amount,
+                state: AccountState::Initialized,
+                ..Default::default()
+            }
+            .pack_into_slice(&mut data);
+            Self::new(key, token::ID, data)
+        }
+
+        fn mint(key: Pubkey) -> Self {
+            let mut data = vec![0u8; SplMint::LEN];
+            SplMint {
+                is_initialized: true,
+                ..Default::default()
+            }
+            .pack_into_slice(&mut data);
+            Self::new(key, token::ID, data)
+        }
+
+        fn signer(key: Pubkey) -> Self {
+            TestAccount {
+                is_signer: true,
+                ..Self::new(key, anchor_lang::system_program::ID, Vec::new())
+            }
+        }
+
+        // Anchor reads an optional account passed as the program id as None
+        fn none() -> Self {
+            Self::new(crate::ID, anchor_lang::system_program::ID, Vec::new())
+        }
+
+        fn token_program() -> Self {
+            TestAccount {
+                executable: true,
+                ..Self::new(token::ID, anchor_lang::system_program::ID, Vec::new())
+            }
+        }
+
+        fn info(&mut self) -> AccountInfo<'_> {
+            AccountInfo::new(
+                &self.key,
+                self.is_signer,
+                true,
+                &mut self.lamports,
+                &mut self.data,
+                &self.owner,
+                self.executable,
+                0,
+            )
+        }
+    }
+
+    // A tokenized stake whose position NFT has moved from `staker` to `buyer`
+    struct TransferredPosition {
+        staker: Pubkey,
+        buyer: Pubkey,
+        pool_key: Pubkey,
+        pool: StakingPool,
+        tier_key: Pubkey,
+        stake_key: Pubkey,
+        stake: UserStake,
+        vault_key: Pubkey,
+        staker_position: Pubkey,
+        buyer_position: Pubkey,
+    }
+
+    impl TransferredPosition {
+        fn new() -> Self {
+            let staker = Pubkey::new_unique();
+            let pool_key = Pubkey::new_unique();
+            let tier_key = Pubkey::new_unique();
+            let mut stake = user_stake(staker, Pubkey::new_unique());
+            stake.pool = pool_key;
+            stake.tier = tier_key;
+            let (stake_key, _) = Pubkey::find_program_address(
+                &[b"user_stake", staker.as_ref(), pool_key.as_ref()],
+                &crate::ID,
+            );
+            let (vault_key, _) =
+                Pubkey::find_program_address(&[b"vault", pool_key.as_ref()], &crate::ID);
+            TransferredPosition {
+                staker,
+                buyer: Pubkey::new_unique(),
+                pool_key,
+                pool: staking_pool(Pubkey::new_unique()),
+                tier_key,
+                stake_key,
+                stake,
+                vault_key,
+                staker_position: Pubkey::new_unique(),
+                buyer_position: Pubkey::new_unique(),
+            }
+        }
+
+        fn tier(&self) -> StakingTier {
+            StakingTier {
+                pool: self.pool_key,
+                duration_days: 0,
+                apy_basis_points: 1000,
+                total_staked: self.stake.amount,
+                is_active: true,
+                apy_history: Vec::new(),
+            }
+        }
+
+        // The buyer's account holds the NFT and the staker's is now empty
+        fn position(&self, key: Pubkey) -> TestAccount {
+            if key == self.buyer_position {
+                TestAccount::token(key, self.stake.position_mint, self.buyer, 1)
+            } else {
+                TestAccount::token(key, self.stake.position_mint, self.staker, 0)
+            }
+        }
+
+        fn claim_accounts(&self, signer: Pubkey, position: Pubkey) -> Vec<TestAccount> {
+            vec![
+                TestAccount::program(self.pool_key, &self.pool),
+                TestAccount::program(self.tier_key, &self.tier()),
+                TestAccount::program(self.stake_key, &self.stake),
+                TestAccount::token(self.vault_key, self.pool.token_mint, self.vault_key, 0),
+                TestAccount::token(Pubkey::new_unique(), self.pool.token_mint, signer, 0),
+                self.position(position),
+                TestAccount::signer(signer),
+                TestAccount::token_program(),
+            ]
+        }
+
+        fn unstake_accounts(&self, signer: Pubkey, position: Pubkey) -> Vec<TestAccount> {
+            vec![
+                TestAccount::program(self.pool_key, &self.pool),
+                TestAccount::program(self.tier_key, &self.tier()),
+                TestAccount::program(self.stake_key, &self.stake),
+                TestAccount::token(self.vault_key, self.pool.token_mint, self.vault_key, 0),
+                TestAccount::token(Pubkey::new_unique(), self.pool.token_mint, signer, 0),
+                TestAccount::mint(self.pool.token_mint),
+                TestAccount::none(),
+                self.position(position),
+                TestAccount::signer(signer),
+                TestAccount::token_program(),
+            ]
+        }
+    }
+
+    // Runs the claim_rewards account validation and owner check
+    fn authorize_claim(mut accounts: Vec<TestAccount>) -> Result<()> {
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+        let mut remaining: &[AccountInfo] = &infos;
+        let ctx = ClaimRewards::try_accounts(
+            &crate::ID,
+            &mut remaining,
+            &[],
+            &mut ClaimRewardsBumps::default(),
+            &mut BTreeSet::new(),
+        )?;
+        verify_stake_owner(
+            &ctx.user_stake,
+            &ctx.user.key(),
+            ctx.position_token_account.as_deref(),
+        )
+    }
+
+    // Runs the unstake_tokens account validation and owner check
+    fn authorize_unstake(mut accounts: Vec<TestAccount>) -> Result<()> {
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+        let mut remaining: &[AccountInfo] = &infos;
+        let ctx = UnstakeTokens::try_accounts(
+            &crate::ID,
+            &mut remaining,
+            &[],
+            &mut UnstakeTokensBumps::default(),
+            &mut BTreeSet::new(),
+        )?;
+        verify_stake_owner(
+            &ctx.user_stake,
+            &ctx.user.key(),
+            ctx.position_token_account.as_deref(),
+        )
+    }
+
+    #[test]
+    fn new_holder_claims_transferred_position() {
+        let position = TransferredPosition::new();
+
+        assert!(authorize_claim(
+            position.claim_accounts(position.buyer, position.buyer_position)
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn new_holder_unstakes_transferred_position() {
+        let position = TransferredPosition::new();
+
+        assert!(authorize_unstake(
+            position.unstake_accounts(position.buyer, position.buyer_position)
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn previous_owner_is_rejected_after_transfer() {
+        let position = TransferredPosition::new();
+
+        // Presenting the emptied account fails the holder check
+        assert_eq!(
+            authorize_claim(position.claim_accounts(position.staker, position.staker_position)),
+            Err(ErrorCode::Unauthorized.into())
+        );
+        assert_eq!(
+            authorize_unstake(
+                position.unstake_accounts(position.staker, position.staker_position)
+            ),
+            Err(ErrorCode::Unauthorized.into())
+        );
+
+        // Presenting the buyer's account fails its authority constraint
+        let not_owner = anchor_lang::error::ErrorCode::ConstraintTokenOwner;
+        assert_eq!(
+            authorize_claim(position.claim_accounts(position.staker, position.buyer_position)),
+            Err(not_owner.into())
+        );
+        assert_eq!(
+            authorize_unstake(position.unstake_accounts(position.staker, position.buyer_position)),
+            Err(not_owner.into())
+        );
+    }
+
+    #[test]
+    fn unverified_nft_boost_is_dropped_and_forfeited() {
+        let staker = Pubkey::new_unique();
+        let mut pool = staking_pool(Pubkey::new_unique());
+        pool.boost_sources.push(BoostSource {
+            kind: BoostKind::NftCollection {
+                collection: Pubkey::new_unique(),
+            },
+            multiplier_bps: 5000,
+        });
+        pool.total_effective_stake = 1_500;
+        pool.acc_reward_per_share = ACC_PRECISION / 5;
+        pool.accumulated_rewards_outstanding = 300;
+
+        let mut stake = user_stake(staker, Pubkey::default());
+        stake.boost_bps = 5000;
+        stake.effective_amount = 1_500;
+
+        // The NFT is no longer presented, so only the unboosted 1x share is earned
+        settle_with_verified_boost(&mut stake, &mut pool, &staker, &[], 0).unwrap();
+        update_reward_weight(&mut stake, &mut pool);
+
+        assert_eq!(stake.boost_bps, 0);
+        assert_eq!(stake.accumulated_rewards, 200);
+        assert_eq!(pool.accumulated_rewards_outstanding, 200);
+        assert_eq!(stake.effective_amount, 1_000);
+        assert_eq!(pool.total_effective_stake, 1_000);
+    }
+
+    #[test]
+    fn loyalty_boosts_stack_up_to_the_cap() {
+        let staker = Pubkey::new_unique();
+        let mut pool = staking_pool(Pubkey::new_unique());
+        for (min_epochs, multiplier_bps) in [(2, 2500), (4, 5000), (8, 5000)] {
+            pool.boost_sources.push(BoostSource {
+                kind: BoostKind::Loyalty { min_epochs },
+                multiplier_bps,
+            });
+        }
+        let mut stake = user_stake(staker, Pubkey::default());
+        stake.staked_since_epoch = 10;
+
+        let boost_at = |epoch| calculate_boost_bps(&pool, &stake, &staker, &[], epoch).unwrap();
+        assert_eq!(boost_at(11), 0);
+        assert_eq!(boost_at(12), 2500);
+        assert_eq!(boost_at(14), 7500);
+        // 12500 bps of sources qualify, but the boost never exceeds MAX_BOOST_BPS
+        assert_eq!(boost_at(18), MAX_BOOST_BPS);
+    }
+
+    fn staking_tier(apy_basis_points: u16) -> StakingTier {
+        StakingTier {
+            pool: Pubkey::new_unique(),
+            duration_days: 365,
+            apy_basis_points,
+            total_staked: 0,
+            is_active: true,
+            apy_history: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn lowering_apy_after_claim_keeps_earned_rewards() {
+        let year = 365 * 24 * 60 * 60;
+        let pool = staking_pool(Pubkey::new_unique());
+        let mut tier = staking_tier(1000);
+        let mut stake = user_stake(Pubkey::new_unique(), Pubkey::default());
+        stake.amount = 1_000_000;
+        stake.end_time = year;
+
+        // Claim half a year at 10%
+        let claimed = calculate_rewards(&stake, &pool, &tier, year / 2).unwrap();
+        assert_eq!(claimed, 50_000);
+        stake.rewards_claimed += claimed;
+
+        // Dropping to 1% only affects the second half; unstaking at the end
+        // pays the remainder instead of underflowing
+        tier.set_apy(100, year / 2);
+        assert_eq!(calculate_rewards(&stake, &pool, &tier, year).unwrap(), 5_000);
+
+        // Raising it back applies from the change onwards
+        tier.set_apy(1000, 3 * year / 4);
+        assert_eq!(calculate_rewards(&stake, &pool, &tier, year).unwrap(), 27_500);
+    }
+
+    #[test]
+    fn full_apy_history_merges_at_lower_rate() {
+        let mut tier = staking_tier(1000);
+        for change in 1..=MAX_APY_CHANGES as i64 + 1 {
+            let apy = if change % 2 == 0 { 1000 } else { 500 };
+            tier.set_apy(apy, change * 10);
+        }
+
+        assert_eq!(tier.apy_history.len(), MAX_APY_CHANGES);
+        // [0, 20) was 1000 then 500; it is now counted entirely at 500
+        assert_eq!(tier.apy_seconds_between(0, 20), 500 * 20);
+        assert_eq!(tier.apy_seconds_between(20, 30), 1000 * 10);
+    }
+
+    #[test]
+    fn epoch_budget_is_split_over_stakes_held_through_the_epoch() {
+        let mut pool = staking_pool(Pubkey::new_unique());
+        pool.reward_mode = RewardMode::Epoch;
+        pool.current_reward_epoch = 1;
+        pool.total_staked = 2_000;
+        pool.epoch_eligible_stake = 2_000;
+
+        let mut alice = user_stake(Pubkey::new_unique(), Pubkey::default());
+        let mut bob = user_stake(Pubkey::new_unique(), Pubkey::default());
+        for stake in [&mut alice, &mut bob] {
+            stake.next_claim_epoch = 1;
+            stake.epoch_amount_from = 1;
+        }
+
+        // Carol joins mid-epoch and holds nothing through epoch 1
+        let mut carol = user_stake(Pubkey::new_unique(), Pubkey::default());
+        carol.next_claim_epoch = 1;
+        carol.epoch_amount_from = 2;
+        pool.total_staked += carol.amount;
+
+        // Alice partially unstakes mid-epoch
+        record_epoch_amount_change(&mut alice, &mut pool, 600).unwrap();
+        alice.amount = 600;
+        pool.total_staked -= 400;
+        assert_eq!(pool.epoch_eligible_stake, 1_600);
+
+        let mut record = EpochRecord {
+            pool: Pubkey::new_unique(),
+            epoch: 1,
+            start_time: 0,
+            end_time: 0,
+            total_staked: pool.total_staked,
+            eligible_stake: pool.epoch_eligible_stake,
+            reward_budget: 1_000,
+            rewards_claimed: 0,
+        };
+        for stake in [&alice, &bob, &carol] {
+            let share = record.share_of(stake.epoch_weight(1));
+            record.rewards_claimed += share;
+        }
+
+        // The whole budget is claimable, not just total_staked's share of it
+        assert_eq!(record.rewards_claimed, 375 + 625);
+        assert_eq!(record.share_of(carol.epoch_weight(1)), 0);
+    }
+
+    #[test]
+    fn emergency_exit_releases_epoch_weight() {
+        let mut pool = staking_pool(Pubkey::new_unique());
+        pool.reward_mode = RewardMode::Epoch;
+        pool.current_reward_epoch = 3;
+        pool.epoch_eligible_stake = 1_000;
+
+        let mut stake = user_stake(Pubkey::new_unique(), Pubkey::default());
+        stake.epoch_amount_from = 2;
+
+        lower_epoch_weight(&mut stake, &mut pool, 0);
+
+        assert_eq!(pool.epoch_eligible_stake, 0);
+        assert_eq!(stake.epoch_weight(3), 0);
+    }
+
+    #[test]
+    fn emergency_exit_ignores_locks_only_while_paused() {
+        let mut pool = staking_pool(Pubkey::new_unique());
+        let mut stake = user_stake(Pubkey::new_unique(), Pubkey::default());
+        stake.vote_locked_until = i64::MAX;
+        stake.vest_locked = true;
+        stake.end_time = i64::MAX;
+        stake.delegate = Pubkey::new_unique();
+
+        assert_eq!(
+            check_emergency_exit(&pool, &stake),
+            Err(ErrorCode::PoolNotPaused.into())
+        );
+        pool.is_paused = true;
+        assert!(check_emergency_exit(&pool, &stake).is_ok());
+
+        stake.is_active = false;
+        assert_eq!(
+            check_emergency_exit(&pool, &stake),
+            Err(ErrorCode::StakeNotActive.into())
+        );
+    }
+
+    #[test]
+    fn emergency_exit_releases_unclaimed_epoch_rewards() {
+        let mut pool = staking_pool(Pubkey::new_unique());
+        pool.reward_mode = RewardMode::Epoch;
+        pool.current_reward_epoch = 3;
+        pool.epoch_rewards_outstanding = 2_000;
+
+        let mut stake = user_stake(Pubkey::new_unique(), Pubkey::default());
+        stake.next_claim_epoch = 1;
+        stake.epoch_amount_from = 1;
+
+        let mut records = [1, 2].map(|epoch| EpochRecord {
+            pool: Pubkey::new_unique(),
+            epoch,
+            start_time: 0,
+            end_time: 0,
+            total_staked: 4_000,
+            eligible_stake: 4_000,
+            reward_budget: 1_000,
+            rewards_claimed: 0,
+        });
+
+        // Epochs must be released in order from the next unclaimed one
+        assert_eq!(
+            release_epoch_share(&mut stake, &mut pool, &mut records[1]),
+            Err(ErrorCode::InvalidEpochRecord.into())
+        );
+        for record in records.iter_mut() {
+            assert_eq!(release_epoch_share(&mut stake, &mut pool, record).unwrap(), 250);
+            assert_eq!(record.rewards_claimed, 250);
+        }
+
+        assert_eq!(stake.next_claim_epoch, 3);
+        assert_eq!(pool.epoch_rewards_outstanding, 1_500);
+    }
+
+    #[test]
+    fn stake_from_another_pool_is_rejected() {
+        let mut position = TransferredPosition::new();
+        // Vested stakes are not at the staker's user_stake address, only the pool is checked
+        position.stake_key = Pubkey::new_unique();
+        assert!(authorize_claim(
+            position.claim_accounts(position.buyer, position.buyer_position)
+        )
+        .is_ok());
+
+        position.stake.pool = Pubkey::new_unique();
+        assert_eq!(
+            authorize_claim(position.claim_accounts(position.buyer, position.buyer_position)),
+            Err(ErrorCode::StakePoolMismatch.into())
+        );
+    }
+
+    #[test]
+    fn referrer_must_be_an_established_staker() {
+        let referee = Pubkey::new_unique();
+        let mut referrer_stake = user_stake(Pubkey::new_unique(), Pubkey::default());
+        referrer_stake.staked_since_epoch = 10;
+
+        assert!(validate_referrer(&referrer_stake, &referee, 10 + MIN_REFERRER_STAKE_EPOCHS).is_ok());
+
+        // A freshly opened second wallet cannot refer straight away
+        assert_eq!(
+            validate_referrer(&referrer_stake, &referee, 9 + MIN_REFERRER_STAKE_EPOCHS),
+            Err(ErrorCode::InvalidReferrer.into())
+        );
+
+        // Nor can a stake refer itself, or the staker who referred it
+        let own_stake = user_stake(referee, Pubkey::default());
+        assert!(validate_referrer(&own_stake, &referee, 100).is_err());
+        referrer_stake.referrer = referee;
+        assert!(validate_referrer(&referrer_stake, &referee, 100).is_err());
+        referrer_stake.referrer = Pubkey::default();
+
+        referrer_stake.is_unbonding = true;
+        assert!(validate_referrer(&referrer_stake, &referee, 100).is_err());
+        referrer_stake.is_unbonding = false;
+
+        referrer_stake.is_active = false;
+        assert!(validate_referrer(&referrer_stake, &referee, 100).is_err());
+    }
+
+    #[test]
+    fn referral_share_is_paid_on_top_of_rewards() {
+        let mut pool = staking_pool(Pubkey::new_unique());
+        pool.referral_bps = 1000;
+        let mut stake = user_stake(Pubkey::new_unique(), Pubkey::default());
+
+        accrue_referral_reward(&mut stake, &mut pool, 5_000);
+        assert_eq!(stake.referral_rewards_owed, 0);
+
+        stake.referrer = Pubkey::new_unique();
+        accrue_referral_reward(&mut stake, &mut pool, 5_000);
+        assert_eq!(stake.referral_rewards_owed, 500);
+        assert_eq!(pool.referral_rewards_outstanding, 500);
+
+        // Turning referrals off stops further accrual but keeps what is owed
+        pool.referral_bps = 0;
+        accrue_referral_reward(&mut stake, &mut pool, 5_000);
+        assert_eq!(stake.referral_rewards_owed, 500);
+        assert_eq!(pool.referral_rewards_outstanding, 500);
+    }
+
+    #[test]
+    fn untokenized_stake_is_owned_by_staker() {
+        let staker = Pubkey::new_unique();
+        let stake = user_stake(staker, Pubkey::default());
+
+        assert!(verify_stake_owner(&stake, &staker, None).is_ok());
+        assert!(verify_stake_owner(&stake, &Pubkey::new_unique(), None).is_err());
+    }
+
+    #[test]
+    fn vested_stake_belongs_to_beneficiary_and_holds_until_its_end() {
+        let beneficiary = Pubkey::new_unique();
+        let payer = Pubkey::new_unique();
+        let mut stake = user_stake(beneficiary, Pubkey::default());
+        stake.end_time = 1_000;
+
+        assert!(verify_stake_owner(&stake, &beneficiary, None).is_ok());
+        assert!(verify_stake_owner(&stake, &payer, None).is_err());
+
+        // Without the flag the lock only decides the early-unstake penalty
+        assert!(stake.vest_unlocked_at(0));
+
+        stake.vest_locked = true;
+        assert!(!stake.vest_unlocked_at(999));
+        assert!(stake.vest_unlocked_at(1_000));
+    }
+
+    #[test]
+    fn transferred_position_can_be_unstaked_by_new_holder() {
+        let staker = Pubkey::new_unique();
+        let buyer = Pubkey::new_unique();
+        let position_mint = Pubkey::new_unique();
+        let stake = user_stake(staker, position_mint);
+
+        // After the NFT moves, the buyer's account holds it and the staker's is empty
+        let buyer_position = token_account(position_mint, buyer, 1);
+        let staker_position = token_account(position_mint, staker, 0);
+
+        assert!(verify_stake_owner(&stake, &buyer, Some(&buyer_position)).is_ok());
+        assert!(verify_stake_owner(&stake, &staker, Some(&staker_position)).is_err());
+        assert!(verify_stake_owner(&stake, &staker, Some(&buyer_position)).is_err());
+        assert!(verify_stake_owner(&stake, &staker, None).is_err());
+    }
+
+    fn metadata(collection: Option<(bool, Pubkey)>) -> Vec<u8> {
+        let mut data = vec![4u8];
+        data.extend_from_slice(Pubkey::new_unique().as_ref());
+        data.extend_from_slice(Pubkey::new_unique().as_ref());
+        for field in ["IAMAI Genesis", "IAMAI", "https://example.com/1.json"] {
+            data.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            data.extend_from_slice(field.as_bytes());
+        }
+        data.extend_from_slice(&500u16.to_le_bytes());
+        // One creator
+        data.push(1);
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(Pubkey::new_unique().as_ref());
+        data.extend_from_slice(&[1, 100]);
+        // primary_sale_happened, is_mutable, edition_nonce, token_standard
+        data.extend_from_slice(&[0, 1, 1, 255, 0]);
+        match collection {
+            Some((verified, key)) => {
+                data.extend_from_slice(&[1, verified as u8]);
+                data.extend_from_slice(key.as_ref());
+            }
+            None => data.push(0),
+        }
+        data
+    }
+
+    #[test]
+    fn reads_verified_collection_from_metadata() {
+        let collection = Pubkey::new_unique();
+
+        assert_eq!(
+            read_verified_collection(&metadata(Some((true, collection)))),
+            Some(collection)
+        );
+        assert_eq!(read_verified_collection(&metadata(Some((false, collection)))), None);
+        assert_eq!(read_verified_collection(&metadata(None)), None);
+        assert_eq!(read_verified_collection(&metadata(None)[..40]), None);
+    }
+
+    #[test]
+    fn escrow_power_reads_checkpoint_in_effect() {
+        let week = 7 * 24 * 60 * 60;
+        let mut escrow = VoteEscrow {
+            owner: Pubkey::new_unique(),
+            pool: Pubkey::new_unique(),
+            amount: 1_000,
+            lock_start: 0,
+            unlock_time: MAX_LOCK_DURATION,
+            history_start: 0,
+            checkpoints: Vec::new(),
+        };
+        escrow.write_checkpoint(0);
+        let before = escrow.voting_power_at(week).unwrap();
+
+        // Topping up and extending later leaves earlier power untouched
+        escrow.amount += 9_000;
+        escrow.write_checkpoint(2 * week);
+        escrow.unlock_time += week;
+        escrow.write_checkpoint(3 * week);
+
+        assert_eq!(escrow.voting_power_at(week).unwrap(), before);
+        assert_eq!(
+            escrow.voting_power_at(2 * week).unwrap(),
+            (10_000u128 * (MAX_LOCK_DURATION - 2 * week) as u128 / MAX_LOCK_DURATION as u128) as u64
+        );
+        assert_eq!(
+            escrow.voting_power_at(3 * week).unwrap(),
+            (10_000u128 * (MAX_LOCK_DURATION - 2 * week) as u128 / MAX_LOCK_DURATION as u128) as u64
+        );
+        assert_eq!(escrow.voting_power_at(-1).unwrap(), 0);
+    }
+
+    #[test]
+    fn escrow_power_errors_before_retained_history() {
+        let mut escrow = VoteEscrow {
+            owner: Pubkey::new_unique(),
+            pool: Pubkey::new_unique(),
+            amount: 1_000,
+            lock_start: 0,
+            unlock_time: MAX_LOCK_DURATION,
+            history_start: 0,
+            checkpoints: Vec::new(),
+        };
+        for timestamp in 0..=MAX_ESCROW_CHECKPOINTS as i64 {
+            escrow.amount += 1;
+            escrow.write_checkpoint(timestamp);
+        }
+
+        assert_eq!(escrow.history_start, 1);
+        assert!(escrow.voting_power_at(0).is_err());
+        assert!(escrow.voting_power_at(1).is_ok());
+    }
+
+    #[test]
+    fn delegate_power_follows_delegators() {
+        let mut record = DelegateRecord {
+            pool: Pubkey::new_unique(),
+            delegate: Pubkey::new_unique(),
+            delegated_power: 0,
+            delegator_count: 0,
+            vote_locked_until: 0,
+        };
+        record.add_power(100);
+        record.add_power(50);
+        assert_eq!((record.delegated_power, record.delegator_count), (150, 2));
+
+        record.remove_power(100);
+        assert_eq!((record.delegated_power, record.delegator_count), (50, 1));
+    }
+
+    #[test]
+    fn position_for_another_mint_is_rejected() {
+        let holder = Pubkey::new_unique();
+        let stake = user_stake(holder, Pubkey::new_unique());
+        let other_position = token_account(Pubkey::new_unique(), holder, 1);
+
+        assert!(verify_stake_owner(&stake, &holder, Some(&other_position)).is_err());
+    }
+
+    #[test]
+    fn extending_to_a_longer_tier_never_shortens_the_lock() {
+        let day = 24 * 60 * 60;
+        let mut long_tier = staking_tier(1000);
+        long_tier.duration_days = 90;
+        let mut stake = user_stake(Pubkey::new_unique(), Pubkey::default());
+
+        // 30 days left on the old lock: the new tier's 90 days start now
+        stake.end_time = 40 * day;
+        assert_eq!(relocked_end_time(&stake, &long_tier, 10 * day), 100 * day);
+
+        // A lock already running past the new tier's duration is kept
+        stake.end_time = 200 * day;
+        assert_eq!(relocked_end_time(&stake, &long_tier, 10 * day), 200 * day);
+    }
+
+    #[test]
+    fn extended_stake_earns_old_apy_then_new_apy() {
+        let year = 365 * 24 * 60 * 60;
+        let pool = staking_pool(Pubkey::new_unique());
+        let old_tier = staking_tier(500);
+        let new_tier = staking_tier(2000);
+        let mut stake = user_stake(Pubkey::new_unique(), Pubkey::default());
+        stake.amount = 1_000_000;
+        stake.end_time = year;
+
+        // Half a year at 5% is paid out when the tier changes
+        let old_rewards = calculate_rewards(&stake, &pool, &old_tier, year / 2).unwrap();
+        assert_eq!(old_rewards, 25_000);
+
+        stake.start_time = year / 2;
+        stake.end_time = relocked_end_time(&stake, &new_tier, year / 2);
+        stake.rewards_claimed = 0;
+        assert_eq!(calculate_rewards(&stake, &pool, &new_tier, year).unwrap(), 100_000);
+    }
+
+    fn reward_token() -> RewardToken {
+        RewardToken {
+            mint: Pubkey::new_unique(),
+            vault: Pubkey::new_unique(),
+            acc_reward_per_share: 0,
+            total_deposited: 0,
+            undistributed: 0,
+        }
+    }
+
+    #[test]
+    fn reward_token_deposits_are_held_until_someone_is_staked() {
+        let mut usdc = reward_token();
+        usdc.distribute(500, 0);
+        assert_eq!(usdc.undistributed, 500);
+        assert_eq!(usdc.acc_reward_per_share, 0);
+
+        usdc.distribute(300, 4_000);
+        assert_eq!(usdc.undistributed, 0);
+        assert_eq!(usdc.acc_reward_per_share, 800 * ACC_PRECISION / 4_000);
+    }
+
+    #[test]
+    fn each_reward_token_accrues_pro_rata_to_effective_stake() {
+        let mut pool = staking_pool(Pubkey::new_unique());
+        pool.reward_tokens = vec![reward_token(), reward_token()];
+        pool.total_effective_stake = 4_000;
+
+        let mut alice = user_stake(Pubkey::new_unique(), Pubkey::default());
+        alice.amount = 1_000;
+        let mut bob = user_stake(Pubkey::new_unique(), Pubkey::default());
+        bob.amount = 2_000;
+        bob.boost_bps = 5000;
+        for stake in [&mut alice, &mut bob] {
+            stake.effective_amount = accumulator_weight(stake);
+        }
+        assert_eq!(alice.effective_amount + bob.effective_amount, 4_000);
+
+        pool.reward_tokens[0].distribute(400, pool.total_effective_stake);
+        pool.reward_tokens[1].distribute(8_000, pool.total_effective_stake);
+        settle_accumulated_rewards(&mut alice, &pool);
+        settle_accumulated_rewards(&mut bob, &pool);
+
+        assert_eq!(alice.reward_token_pending[..2], [100, 2_000]);
+        assert_eq!(bob.reward_token_pending[..2], [300, 6_000]);
+
+        // Settling again without new deposits pays nothing twice
+        settle_accumulated_rewards(&mut alice, &pool);
+        assert_eq!(alice.reward_token_pending[..2], [100, 2_000]);
+    }
+
+    #[test]
+    fn partial_unstake_settles_the_full_amount_then_accrues_on_the_rest() {
+        let year = 365 * 24 * 60 * 60;
+        let mut pool = staking_pool(Pubkey::new_unique());
+        pool.early_unstake_penalty = 1000;
+        let tier = staking_tier(1000);
+        let mut stake = user_stake(Pubkey::new_unique(), Pubkey::default());
+        stake.amount = 1_000_000;
+        stake.end_time = year;
+
+        // Half a year in, 400_000 is withdrawn early
+        let rewards = settle_rewards(&mut stake, &mut pool, &tier, year / 2).unwrap();
+        assert_eq!(rewards, 50_000);
+        assert_eq!(stake.start_time, year / 2);
+        assert_eq!(early_unstake_penalty(&pool, 400_000), 40_000);
+
+        stake.amount -= 400_000;
+        assert_eq!(calculate_rewards(&stake, &pool, &tier, year).unwrap(), 30_000);
+    }
+
+    #[test]
+    fn top_up_after_the_lock_ended_earns_nothing_more() {
+        let year = 365 * 24 * 60 * 60;
+        let mut pool = staking_pool(Pubkey::new_unique());
+        let tier = staking_tier(1000);
+        let mut stake = user_stake(Pubkey::new_unique(), Pubkey::default());
+        stake.amount = 1_000_000;
+        stake.end_time = year;
+
+        // Topping up after the lock without resetting it restarts accrual
+        // past end_time, which must not underflow
+        assert_eq!(settle_rewards(&mut stake, &mut pool, &tier, 2 * year).unwrap(), 100_000);
+        stake.amount += 500_000;
+        assert_eq!(calculate_rewards(&stake, &pool, &tier, 3 * year).unwrap(), 0);
+    }
+
+    #[test]
+    fn early_penalty_is_split_from_the_withdrawn_amount() {
+        let mut pool = staking_pool(Pubkey::new_unique());
+        pool.early_unstake_penalty = 1500;
+
+        let penalty = early_unstake_penalty(&pool, 1_000_000);
+        assert_eq!(penalty, 150_000);
+        assert_eq!(1_000_000 - penalty, 850_000);
+        // Large stakes don't overflow the intermediate product
+        assert!(early_unstake_penalty(&pool, u64::MAX) > u64::MAX / 7);
+    }
+
+    #[test]
+    fn redistributed_penalty_is_shared_pro_rata() {
+        let mut pool = staking_pool(Pubkey::new_unique());
+        pool.penalty_destination = PenaltyDestination::Redistribute;
+        pool.total_effective_stake = 4_000;
+
+        let mut alice = user_stake(Pubkey::new_unique(), Pubkey::default());
+        alice.effective_amount = 1_000;
+        let mut bob = user_stake(Pubkey::new_unique(), Pubkey::default());
+        bob.effective_amount = 3_000;
+
+        assert!(penalty_destination(&pool) == PenaltyDestination::Redistribute);
+        redistribute_penalty(&mut pool, 400);
+        settle_accumulated_rewards(&mut alice, &pool);
+        settle_accumulated_rewards(&mut bob, &pool);
+
+        assert_eq!(alice.accumulated_rewards, 100);
+        assert_eq!(bob.accumulated_rewards, 300);
+        assert_eq!(pool.accumulated_rewards_outstanding, 400);
+    }
+
+    #[test]
+    fn penalty_is_burned_when_nobody_is_left_to_share_it() {
+        let mut pool = staking_pool(Pubkey::new_unique());
+        pool.penalty_destination = PenaltyDestination::Redistribute;
+        pool.total_effective_stake = 0;
+        assert!(penalty_destination(&pool) == PenaltyDestination::Burn);
+
+        pool.penalty_destination = PenaltyDestination::Treasury;
+        assert!(penalty_destination(&pool) == PenaltyDestination::Treasury);
+    }
+
+    #[test]
+    fn unbonding_stake_stops_earning() {
+        let year = 365 * 24 * 60 * 60;
+        let mut pool = staking_pool(Pubkey::new_unique());
+        let tier = staking_tier(1000);
+        let mut stake = user_stake(Pubkey::new_unique(), Pubkey::default());
+        stake.amount = 1_000_000;
+        stake.end_time = year;
+        stake.effective_amount = 1_000_000;
+        pool.total_effective_stake = 1_000_000;
+
+        // Unbonding a quarter of the way through the lock
+        stake.is_unbonding = true;
+        stake.unbonding_start = year / 4;
+        update_reward_weight(&mut stake, &mut pool);
+
+        assert_eq!(calculate_rewards(&stake, &pool, &tier, year).unwrap(), 25_000);
+        assert_eq!(stake.effective_amount, 0);
+        assert_eq!(pool.total_effective_stake, 0);
+    }
+
+    #[test]
+    fn liquid_round_trip_never_returns_more_than_deposited() {
+        let (receipt_supply, vault_amount) = (5_000_000_000, 6_000_000_000);
+        let deposit = 12_345_678;
+
+        let receipts = convert_liquid_amount(deposit, receipt_supply, vault_amount).unwrap();
+        let redeemed =
+            convert_liquid_amount(receipts, vault_amount + deposit, receipt_supply + receipts)
+                .unwrap();
+        assert!(redeemed <= deposit && deposit - redeemed <= 2);
+    }
+
+    #[test]
+    fn donation_to_empty_liquid_vault_does_not_pay() {
+        // Attacker takes the first receipt, then donates to the vault
+        let attacker_receipts = convert_liquid_amount(1, 0, 0).unwrap();
+        let donation = 1_000_000_000;
+        let mut vault_amount = 1 + donation;
+        let mut receipt_supply = attacker_receipts;
+
+        let deposit = 1_000_000_000;
+        let victim_receipts = convert_liquid_amount(deposit, receipt_supply, vault_amount).unwrap();
+        assert!(victim_receipts > 0);
+        vault_amount += deposit;
+        receipt_supply += victim_receipts;
+
+        let victim_out = convert_liquid_amount(victim_receipts, vault_amount, receipt_supply).unwrap();
+        vault_amount -= victim_out;
+        receipt_supply -= victim_receipts;
+        let attacker_out = convert_liquid_amount(attacker_receipts, vault_amount, receipt_supply).unwrap();
+
+        // The victim loses less than 0.001%; the attacker loses the donation
+        assert!(deposit - victim_out < deposit / 100_000);
+        assert!(attacker_out < donation / 1_000);
+    }
+
+    #[test]
+    fn liquid_principal_leaves_pro_rata_and_fully_at_last_redemption() {
+        assert_eq!(liquid_principal_share(250, 1_000, 1_000), 250);
+        assert_eq!(liquid_principal_share(1, 1_000, 3), 333);
+        assert_eq!(liquid_principal_share(3, 1_000, 3), 1_000);
+    }
 }